@@ -0,0 +1,199 @@
+// Plain timing loops over the core paths. No criterion: keep the tree
+// building offline. Inputs are fixed (no randomness), so runs on the same
+// machine are comparable with each other.
+//
+// cargo bench --bench core
+//
+// Baseline, one-vCPU Xeon VM, rustc 1.95. The node benches share that CPU
+// between the clients and the spinning shards, so they mostly measure
+// scheduler hand-offs; expect far better on a real multi-core box.
+//
+//   local put                           145.4 ns/op
+//   local get (hit)                     127.0 ns/op
+//   local get (miss)                     34.4 ns/op
+//   local get+clone (4 KiB inline)      242.3 ns/op
+//   local get+clone (4 KiB Arc)          35.4 ns/op
+//   put requests (staging 0)            165.0 ns/op
+//   put requests (staging 64)           133.8 ns/op
+//   route (1 shards)                      2.2 ns/op
+//   route (16 shards)                    13.1 ns/op
+//   ring push+pop                         3.1 ns/op
+//   mixed 90/10 (1 threads)           40122.6 ns/op
+//   mixed 90/10 (4 threads)           10320.0 ns/op
+//   queued puts, one shard              782.5 ns/op
+
+use std::{
+    hint::black_box,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use kv_store::{core_affinity::CoreId, LocalNode, Node, NodeConfig, NodeHandle, Request};
+use rtrb::RingBuffer;
+
+const OPS: u64 = 1_000_000;
+
+fn report(name: &str, ops: u64, elapsed: Duration) {
+    let per_op = elapsed.as_nanos() as f64 / ops as f64;
+    let per_sec = ops as f64 / elapsed.as_secs_f64();
+    println!("{name:<32} {per_op:>8.1} ns/op {per_sec:>14.0} ops/s");
+}
+
+fn bench(name: &str, ops: u64, mut f: impl FnMut()) {
+    let start = Instant::now();
+    f();
+    report(name, ops, start.elapsed());
+}
+
+fn local_put_get() {
+    let mut node = LocalNode::<u64, u64>::new();
+
+    bench("local put", OPS, || {
+        for k in 0..OPS {
+            node.put(k, k);
+        }
+    });
+
+    bench("local get (hit)", OPS, || {
+        for k in 0..OPS {
+            black_box(node.get(&k));
+        }
+    });
+
+    bench("local get (miss)", OPS, || {
+        for k in OPS..2 * OPS {
+            black_box(node.get(&k));
+        }
+    });
+}
+
+// PUT requests into a fresh shard, inserted one by one vs staged in batches
+fn staged_puts(staging: usize) {
+    let mut node = LocalNode::<u64, u64>::new();
+    node.shard().set_write_staging(staging);
+
+    bench(&format!("put requests (staging {staging})"), OPS, || {
+        for k in 0..OPS {
+            node.shard().handle_request(Request::PUT(k, k)).ok();
+        }
+        node.shard().flush_staged();
+    });
+}
+
+// an owned copy of a 4 KiB value per read, inline vs behind an Arc
+fn large_values() {
+    const KEYS: u64 = 1_000;
+    const READS: u64 = 100_000;
+
+    let mut inline = LocalNode::<u64, Vec<u8>>::new();
+    let mut shared = LocalNode::<u64, Arc<Vec<u8>>>::new();
+    for k in 0..KEYS {
+        inline.put(k, vec![k as u8; 4096]);
+        shared.put(k, Arc::new(vec![k as u8; 4096]));
+    }
+
+    bench("local get+clone (4 KiB inline)", READS, || {
+        for k in 0..READS {
+            black_box(inline.get(&(k % KEYS)).cloned());
+        }
+    });
+
+    bench("local get+clone (4 KiB Arc)", READS, || {
+        for k in 0..READS {
+            black_box(shared.get(&(k % KEYS)).cloned());
+        }
+    });
+}
+
+fn routing(num_shards: usize) {
+    let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; num_shards]), ..Default::default() };
+    let node = Node::<u64, u64>::with_config(0, config);
+
+    bench(&format!("route ({num_shards} shards)"), OPS, || {
+        for k in 0..OPS {
+            black_box(node.route(&k));
+        }
+    });
+}
+
+fn ring_buffer() {
+    let (mut prod, mut cons) = RingBuffer::<u64>::new(kv_store::QUEUE_CAPACITY);
+
+    bench("ring push+pop", OPS, || {
+        for k in 0..OPS {
+            prod.push(k).ok();
+            black_box(cons.pop().ok());
+        }
+    });
+}
+
+fn started(num_shards: usize, keys: u64) -> NodeHandle<u64, u64> {
+    let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; num_shards]), ..Default::default() };
+    let mut node = Node::<u64, u64>::with_config(0, config);
+    for k in 0..keys {
+        node.preload(k, k).unwrap();
+    }
+    let handle = node.start().unwrap();
+    handle.wait_ready();
+    handle
+}
+
+// client threads on a running node, each doing nine gets to one queued put
+fn mixed_load(threads: u64) {
+    const KEYS: u64 = 10_000;
+    const PER_THREAD: u64 = 20_000;
+
+    let handle = started(2, KEYS);
+    bench(&format!("mixed 90/10 ({threads} threads)"), threads * PER_THREAD, || {
+        std::thread::scope(|s| {
+            for t in 0..threads {
+                let handle = &handle;
+                s.spawn(move || {
+                    for i in 0..PER_THREAD {
+                        let k = (t * 7_919 + i * 31) % KEYS;
+                        if i % 10 == 9 {
+                            let shard = handle.route(&k);
+                            while handle.send_shard(shard, Request::PUT(k, i)).is_err() {
+                                std::thread::yield_now();
+                            }
+                        } else {
+                            black_box(handle.get(k));
+                        }
+                    }
+                });
+            }
+        });
+    });
+    handle.shutdown();
+}
+
+// PUTs pushed into one shard's ingress queue as fast as it drains them,
+// timed until a GET queued behind them comes back
+fn queue_throughput() {
+    let handle = started(1, 0);
+    bench("queued puts, one shard", OPS, || {
+        for k in 0..OPS {
+            while handle.send_shard(0, Request::PUT(k, k)).is_err() {
+                std::thread::yield_now();
+            }
+        }
+        black_box(handle.get(OPS - 1));
+    });
+    handle.shutdown();
+}
+
+fn main() {
+    local_put_get();
+    large_values();
+    for staging in [0, 64] {
+        staged_puts(staging);
+    }
+    for num_shards in [1, 2, 4, 8, 16] {
+        routing(num_shards);
+    }
+    ring_buffer();
+    for threads in [1, 2, 4] {
+        mixed_load(threads);
+    }
+    queue_throughput();
+}
@@ -0,0 +1,32 @@
+// construct -> inspect/preload -> start -> wait_ready -> read -> shutdown
+use kv_store::{Node, Request};
+
+fn main() {
+    let mut node = Node::<u64, String>::new(0);
+
+    for (shard, core) in node.placement().iter().enumerate() {
+        println!("shard {shard} -> core {}", core.id);
+    }
+
+    // straight into the owning shard before anything is running
+    node.preload(1, "one".to_string()).expect("preload failed");
+
+    // queued now, served once the shards start
+    let key = 2;
+    node.send_shard(node.route(&key), Request::PUT(key, "two".to_string())).expect("queue full");
+    let last = 3;
+    let last_shard = node.route(&last);
+
+    let handle = node.start().expect("failed to start node");
+    handle.wait_ready();
+    println!("node {} ready", handle.id());
+
+    assert_eq!(handle.get(1).as_deref(), Some("one"));
+    assert_eq!(handle.get(key).as_deref(), Some("two"));
+    println!("read back both keys");
+
+    // shutdown serves what's already queued before the shards stop
+    handle.send_shard(last_shard, Request::PUT(last, "three".to_string())).expect("queue full");
+    handle.shutdown();
+    println!("node stopped");
+}
@@ -0,0 +1,72 @@
+//! Per-shard bloom filter so GETs for absent keys can skip the map.
+//!
+//! Bloom filters can't forget keys, so deletes leave stale bits behind that
+//! only cost extra false positives. The shard rebuilds the filter from its
+//! live keys once enough deletes have piled up.
+
+use std::hash::Hash;
+
+use crate::routing::hash_key;
+
+/// Sizing for a shard's bloom filter.
+#[derive(Debug, Clone, Copy)]
+pub struct BloomConfig {
+    /// Keys per shard the filter is sized for. Going past this raises the
+    /// false-positive rate but never causes false negatives.
+    pub expected_items      : usize,
+    /// Target chance that an absent key still has to be looked up in the map.
+    pub false_positive_rate : f64,
+}
+
+pub(crate) struct BloomFilter {
+    bits    : Vec<u64>,
+    hashes  : u32,
+    deletes : usize,
+    config  : BloomConfig,
+}
+
+impl BloomFilter {
+    pub(crate) fn new(config: BloomConfig) -> Self {
+        let items = config.expected_items.max(1) as f64;
+        let rate = config.false_positive_rate.clamp(1e-9, 0.5);
+
+        // standard optimum: m = -n ln p / ln2^2, k = m/n ln2
+        let num_bits = (-items * rate.ln() / (2f64.ln() * 2f64.ln())).ceil().max(64.0) as usize;
+        let hashes = ((num_bits as f64 / items) * 2f64.ln()).round().max(1.0) as u32;
+
+        BloomFilter { bits: vec![0; num_bits.div_ceil(64)], hashes, deletes: 0, config }
+    }
+
+    pub(crate) fn insert<K: Hash>(&mut self, key: &K) {
+        for bit in self.bit_indexes(key) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// `false` means the key is definitely absent.
+    pub(crate) fn may_contain<K: Hash>(&self, key: &K) -> bool {
+        self.bit_indexes(key).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Counts a delete; returns `true` once the filter is stale enough to rebuild.
+    pub(crate) fn note_delete(&mut self) -> bool {
+        self.deletes += 1;
+        self.deletes >= self.config.expected_items.max(4) / 4
+    }
+
+    pub(crate) fn rebuild<'a, K: Hash + 'a>(&mut self, keys: impl Iterator<Item = &'a K>) {
+        self.bits.iter_mut().for_each(|word| *word = 0);
+        self.deletes = 0;
+        for key in keys {
+            self.insert(key);
+        }
+    }
+
+    // double hashing: h1 + i*h2 over the bit array
+    fn bit_indexes<K: Hash>(&self, key: &K) -> impl Iterator<Item = usize> + use<K> {
+        let hash = hash_key(key);
+        let (h1, h2) = (hash as u32 as u64, (hash >> 32) | 1);
+        let num_bits = (self.bits.len() * 64) as u64;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+}
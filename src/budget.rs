@@ -0,0 +1,63 @@
+//! Node-wide ceiling on the number of keys.
+//!
+//! Every shard leases room for new keys from one shared counter, `batch` keys
+//! at a time, so the shards touch the shared cache line once per batch rather
+//! than once per insert. Keys plus unused leases never exceed the cap, so the
+//! cap is hard. The slack shows up as inserts being rejected early while
+//! another shard still holds unused room (at most `batch` per shard).
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+pub(crate) struct KeyBudget {
+    used   : Arc<AtomicUsize>,
+    max    : usize,
+    batch  : usize,
+    leased : usize,
+}
+
+impl KeyBudget {
+    pub(crate) fn new(used: Arc<AtomicUsize>, max: usize, batch: usize) -> Self {
+        KeyBudget { used, max, batch: batch.max(1), leased: 0 }
+    }
+
+    /// Takes room for one new key, or `false` if the node is full.
+    pub(crate) fn claim(&mut self) -> bool {
+        if self.leased == 0 {
+            let mut used = self.used.load(Ordering::Acquire);
+            loop {
+                let room = self.max.saturating_sub(used).min(self.batch);
+                if room == 0 {
+                    return false;
+                }
+                match self.used.compare_exchange_weak(used, used + room, Ordering::AcqRel, Ordering::Acquire) {
+                    Ok(_) => {
+                        self.leased = room;
+                        break;
+                    },
+                    Err(current) => used = current,
+                }
+            }
+        }
+        self.leased -= 1;
+        true
+    }
+
+    /// Counts a new key even if that goes over the cap.
+    pub(crate) fn force(&mut self) {
+        if !self.claim() {
+            self.used.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    /// Gives back the room of a removed key.
+    pub(crate) fn release(&mut self) {
+        self.leased += 1;
+        if self.leased > 2 * self.batch {
+            self.used.fetch_sub(self.leased - self.batch, Ordering::AcqRel);
+            self.leased = self.batch;
+        }
+    }
+}
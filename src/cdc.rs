@@ -0,0 +1,83 @@
+//! Change data capture: a compact record of every write, per shard.
+//!
+//! Each shard pushes into its own bounded ring buffer, so records are in total
+//! order within a shard but there is no order across shards. A consumer that
+//! falls behind loses records rather than stalling the shard; `dropped()`
+//! reports how many.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use rtrb::{Consumer, Producer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Put,
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChangeRecord {
+    pub shard    : usize,
+    pub key_hash : u64,
+    pub kind     : ChangeKind,
+    /// Per-shard sequence number, starting at 1.
+    pub version  : u64,
+}
+
+pub(crate) struct ChangeProducer {
+    queue   : Producer<ChangeRecord>,
+    next    : u64,
+    dropped : Arc<AtomicU64>,
+}
+
+impl ChangeProducer {
+    pub(crate) fn new(queue: Producer<ChangeRecord>, dropped: Arc<AtomicU64>) -> Self {
+        ChangeProducer { queue, next: 1, dropped }
+    }
+
+    pub(crate) fn record(&mut self, shard: usize, key_hash: u64, kind: ChangeKind) {
+        let record = ChangeRecord { shard, key_hash, kind, version: self.next };
+        self.next += 1;
+        if self.queue.push(record).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Drains the change streams of every shard of a node.
+pub struct ChangeConsumer {
+    queues  : Vec<Consumer<ChangeRecord>>,
+    next    : usize,
+    dropped : Arc<AtomicU64>,
+}
+
+impl ChangeConsumer {
+    pub(crate) fn new(queues: Vec<Consumer<ChangeRecord>>, dropped: Arc<AtomicU64>) -> Self {
+        ChangeConsumer { queues, next: 0, dropped }
+    }
+
+    /// Next available record, visiting shards round-robin.
+    pub fn try_recv(&mut self) -> Option<ChangeRecord> {
+        for _ in 0..self.queues.len() {
+            let current = self.next;
+            self.next = (self.next + 1) % self.queues.len();
+            if let Ok(record) = self.queues[current].pop() {
+                return Some(record);
+            }
+        }
+        None
+    }
+
+    /// Everything currently queued across all shards.
+    pub fn drain(&mut self) -> Vec<ChangeRecord> {
+        std::iter::from_fn(|| self.try_recv()).collect()
+    }
+
+    /// Records lost because this consumer fell behind.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
@@ -28,7 +28,6 @@
 //! }
 //! ```
 
-#[allow(warnings)]
 #[cfg(any(
     target_os = "android",
     target_os = "linux",
@@ -55,6 +54,125 @@ pub fn set_for_current(core_id: CoreId) -> bool {
     set_for_current_helper(core_id)
 }
 
+/// One past the highest core id this platform's
+/// affinity masks can hold. `set_for_current`
+/// fails for ids at or above it.
+#[cfg(any(
+    target_os = "android",
+    target_os = "linux",
+    target_os = "freebsd"
+))]
+pub const CORE_ID_LIMIT: usize = libc::CPU_SETSIZE as usize;
+
+/// One past the highest core id this platform's
+/// affinity masks can hold. `set_for_current`
+/// fails for ids at or above it.
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "linux",
+    target_os = "freebsd"
+)))]
+pub const CORE_ID_LIMIT: usize = usize::MAX;
+
+/// Whether this platform has an affinity backend.
+/// Everywhere else `get_core_ids` is `None` and
+/// `set_for_current` always fails, so nodes run
+/// unpinned there without trying.
+pub const fn is_supported() -> bool {
+    cfg!(any(
+        target_os = "android",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "netbsd"
+    ))
+}
+
+/// This function returns the cores the current
+/// thread may run on right now, to check that
+/// a pin took effect. `None` where the platform
+/// can't say, including macOS.
+///
+/// ```
+/// # #[cfg(target_os = "linux")]
+/// std::thread::spawn(|| {
+///     use kv_store::core_affinity;
+///
+///     let core = core_affinity::get_core_ids().unwrap()[0];
+///     assert!(core_affinity::set_for_current(core));
+///     assert_eq!(core_affinity::current_affinity(), Some(vec![core]));
+/// }).join().unwrap();
+/// ```
+pub fn current_affinity() -> Option<Vec<CoreId>> {
+    current_affinity_helper()
+}
+
+// these backends already read the calling thread's mask
+#[cfg(any(
+    target_os = "android",
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "netbsd"
+))]
+#[inline]
+fn current_affinity_helper() -> Option<Vec<CoreId>> {
+    get_core_ids_helper()
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "netbsd"
+)))]
+#[inline]
+fn current_affinity_helper() -> Option<Vec<CoreId>> {
+    None
+}
+
+/// How cores map onto macOS affinity tags. macOS has no hard pinning: a tag
+/// only asks the scheduler to keep threads with equal tags on a shared L2
+/// and threads with different tags apart, and it's free to ignore that.
+/// Other platforms pin for real and ignore this.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum AffinityTags {
+    /// A tag per core, spreading threads out as far as macOS allows.
+    #[default]
+    PerCore,
+    /// One tag per `n` consecutive core ids, so their threads share cache.
+    Grouped(usize),
+}
+
+impl AffinityTags {
+    /// The tag for `core_id`. Tags start at 1, since 0 means "no affinity".
+    ///
+    /// ```
+    /// use kv_store::core_affinity::{AffinityTags, CoreId};
+    ///
+    /// let tags = |tags: AffinityTags| (0..4).map(|id| tags.tag(CoreId { id })).collect::<Vec<_>>();
+    /// assert_eq!(tags(AffinityTags::PerCore), [1, 2, 3, 4]);
+    /// assert_eq!(tags(AffinityTags::Grouped(2)), [1, 1, 2, 2]);
+    /// ```
+    pub fn tag(self, core_id: CoreId) -> i32 {
+        let group = match self {
+            AffinityTags::PerCore => core_id.id,
+            AffinityTags::Grouped(n) => core_id.id / n.max(1),
+        };
+        group as i32 + 1
+    }
+}
+
+/// Sets the current thread's macOS affinity tag, see `AffinityTags`.
+///
+/// ```
+/// # #[cfg(target_os = "macos")]
+/// assert!(kv_store::core_affinity::set_tag_for_current(1));
+/// ```
+#[cfg(target_os = "macos")]
+pub fn set_tag_for_current(tag: i32) -> bool {
+    macos::set_tag_for_current(tag)
+}
+
 /// This represents a CPU core.
 #[repr(transparent)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -101,6 +219,11 @@ mod linux {
     }
 
     pub fn set_for_current(core_id: CoreId) -> bool {
+        // CPU_SET can't hold ids past the end of the mask
+        if core_id.id >= CPU_SETSIZE as usize {
+            return false;
+        }
+
         // Turn `core_id` into a `libc::cpu_set_t` with only
         // one core active.
         let mut set = new_cpu_set();
@@ -188,7 +311,7 @@ mod macos {
 
     pub fn get_core_ids() -> Option<Vec<CoreId>> {
         Some(
-            (0..(num_cpus::detect()))
+            (0..num_cpus::detect_typed().as_usize())
                 .into_iter()
                 .map(|n| CoreId { id: n as usize })
                 .collect::<Vec<_>>(),
@@ -196,12 +319,16 @@ mod macos {
     }
 
     pub fn set_for_current(core_id: CoreId) -> bool {
+        set_tag_for_current(super::AffinityTags::PerCore.tag(core_id))
+    }
+
+    pub fn set_tag_for_current(tag: integer_t) -> bool {
         let THREAD_AFFINITY_POLICY_COUNT: mach_msg_type_number_t =
             mem::size_of::<thread_affinity_policy_data_t>() as mach_msg_type_number_t
                 / mem::size_of::<integer_t>() as mach_msg_type_number_t;
 
         let mut info = thread_affinity_policy_data_t {
-            affinity_tag: core_id.id as integer_t,
+            affinity_tag: tag,
         };
 
         let res = unsafe {
@@ -258,6 +385,11 @@ mod freebsd {
     }
 
     pub fn set_for_current(core_id: CoreId) -> bool {
+        // CPU_SET can't hold ids past the end of the mask
+        if core_id.id >= CPU_SETSIZE as usize {
+            return false;
+        }
+
         // Turn `core_id` into a `libc::cpuset_t` with only
         // one core active.
         let mut set = new_cpu_set();
@@ -372,7 +504,6 @@ mod netbsd {
 #[cfg(not(any(
     target_os = "linux",
     target_os = "android",
-    target_os = "windows",
     target_os = "macos",
     target_os = "freebsd",
     target_os = "netbsd"
@@ -385,7 +516,6 @@ fn get_core_ids_helper() -> Option<Vec<CoreId>> {
 #[cfg(not(any(
     target_os = "linux",
     target_os = "android",
-    target_os = "windows",
     target_os = "macos",
     target_os = "freebsd",
     target_os = "netbsd"
@@ -0,0 +1,66 @@
+//! Hands out disjoint sets of cores so several nodes in one process don't
+//! pin their shards on top of each other.
+//!
+//! ## Example
+//!
+//! ```
+//! use kv_store::{core_affinity::CoreId, core_pool::CorePool};
+//!
+//! let pool = CorePool::new((0..4).map(|id| CoreId { id }).collect());
+//!
+//! let a = pool.allocate(2).unwrap();
+//! let b = pool.allocate(2).unwrap();
+//! assert!(a.iter().all(|core| !b.contains(core)));
+//!
+//! // nothing left for a third node
+//! assert!(pool.allocate(1).is_none());
+//! ```
+
+use std::sync::{Mutex, OnceLock};
+
+use crate::core_affinity::{self, CoreId};
+use crate::num_cores::num_cpus;
+
+pub struct CorePool {
+    free: Mutex<Vec<CoreId>>,
+}
+
+impl CorePool {
+    pub fn new(mut cores: Vec<CoreId>) -> Self {
+        cores.sort_unstable();
+        cores.dedup();
+        CorePool { free: Mutex::new(cores) }
+    }
+
+    /// The process-wide pool, seeded with the cores this process may run on.
+    pub fn global() -> &'static CorePool {
+        static GLOBAL: OnceLock<CorePool> = OnceLock::new();
+        GLOBAL.get_or_init(|| {
+            let cores = core_affinity::get_core_ids()
+                .unwrap_or_else(|| (0..num_cpus::detect()).map(Into::into).collect());
+            CorePool::new(cores)
+        })
+    }
+
+    /// Takes `n` cores out of the pool, lowest ids first. Returns `None` and
+    /// takes nothing if fewer than `n` are free.
+    pub fn allocate(&self, n: usize) -> Option<Vec<CoreId>> {
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        if free.len() < n {
+            return None;
+        }
+        Some(free.drain(..n).collect())
+    }
+
+    /// Returns cores to the pool, e.g. once the node using them has stopped.
+    pub fn release(&self, cores: Vec<CoreId>) {
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        free.extend(cores);
+        free.sort_unstable();
+        free.dedup();
+    }
+
+    pub fn available(&self) -> usize {
+        self.free.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
@@ -1,15 +1,22 @@
 use std::{
-    collections::HashMap,
+    collections::{hash_map, HashMap},
     fmt::Debug,
     hash::Hash,
+    sync::{atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}, mpsc, Arc, Condvar, Mutex},
     thread::{self, JoinHandle}
 };
 
-use rtrb::{RingBuffer, Consumer, Producer};
+use rtrb::{PushError, RingBuffer, Consumer, Producer};
 use thiserror::Error;
 
+pub mod bloom;
+mod budget;
+pub mod cdc;
 pub mod core_affinity;
+pub mod core_pool;
 pub mod num_cores;
+pub mod routing;
+pub mod stats;
 
 impl From<usize> for core_affinity::CoreId {
     fn from(value: usize) -> Self {
@@ -17,34 +24,802 @@ impl From<usize> for core_affinity::CoreId {
     }
 }
 
-use num_cores::num_cpus;
+use bloom::{BloomConfig, BloomFilter};
+use budget::KeyBudget;
+use cdc::{ChangeConsumer, ChangeKind, ChangeProducer};
+use num_cores::{num_cpus, numa};
+use routing::Router;
+use stats::{ShardStats, StatsSnapshot};
 
 pub static CLUSTER_MAX: usize = 0;
 
+/// Slots in each shard-to-shard ring buffer.
+pub const QUEUE_CAPACITY: usize = 100;
+
+/// Default for `NodeConfig::max_shards`.
+pub const MAX_SHARDS: usize = 1024;
+
+/// What `Node::for_throughput` assumes one shard sustains. Taken from the
+/// `core` bench with small keys and values: a local put is ~170ns, and this
+/// leaves room for the queue hop and routing on top.
+pub const SHARD_OPS_PER_SEC: u64 = 2_000_000;
+
 pub trait Key: Hash + Eq + Send + Sync + 'static {}
 impl<T: Hash + Eq + Send + Sync + 'static> Key for T {}
 
-pub trait Value: Hash + Eq + Send + Sync + 'static {}
-impl<T: Hash + Eq + Send + Sync + 'static> Value for T {}
+/// Anything storable. Values are cloned wherever a copy has to leave the
+/// shard, so large read-mostly values are better stored as `Arc<T>`: a clone
+/// is then a refcount bump, and every reader shares one allocation.
+///
+/// ```
+/// use std::sync::Arc;
+/// use kv_store::LocalNode;
+///
+/// let mut node = LocalNode::<&str, Arc<Vec<u8>>>::new();
+/// node.put("blob", Arc::new(vec![0; 1 << 20]));
+///
+/// let a = node.get(&"blob").cloned().unwrap();
+/// let b = node.get(&"blob").cloned().unwrap();
+/// assert!(Arc::ptr_eq(&a, &b));
+/// ```
+pub trait Value: Clone + Hash + Eq + Send + Sync + 'static {}
+impl<T: Clone + Hash + Eq + Send + Sync + 'static> Value for T {}
 
 #[derive(Error, Debug)]
 pub enum KVError {
     #[error("unknown error occurred")]
     Unknown,
+    #[error("failed to pin shards {0:?}")]
+    AffinityFailed(Vec<usize>),
+    #[error("keys span more than one shard")]
+    CrossShard,
+    #[error("batch is empty")]
+    EmptyBatch,
+    #[error("queue to shard {dst} is full")]
+    QueueFull { dst: usize },
+    #[error("no queue from shard {src} to shard {dst}")]
+    NoRoute { src: usize, dst: usize },
+    #[error("no shard {0}")]
+    UnknownShard(usize),
+    #[error("no function registered as {0:?}")]
+    UnknownFunction(String),
+    #[error("node is at its key limit")]
+    CapacityFull,
+    #[error("{shards} shards is over the limit of {max}")]
+    TooManyShards { shards: usize, max: usize },
+    #[error("node is read-only")]
+    ReadOnly,
+    #[error("key appears more than once in the batch")]
+    DuplicateKey,
+    #[error("invalid config: {0}")]
+    InvalidConfig(&'static str),
+    #[error("stored value for key hash {key_hash:#x} failed its checksum")]
+    DataCorruption { key_hash: u64 },
+    #[error("failed to spawn shard thread: {0}")]
+    Spawn(#[from] std::io::Error),
+    #[error("a shard thread died before it finished starting")]
+    ShardDied,
 }
 
 type KVResult<T> = Result<T, KVError>;
 
+/// What a shard does on a pass that found no requests.
+///
+/// ```
+/// use std::time::Duration;
+/// use kv_store::{IdleStrategy, Node, NodeConfig};
+///
+/// for idle_strategy in [IdleStrategy::Sleep(Duration::from_millis(1)), IdleStrategy::Yield, IdleStrategy::NoYield] {
+///     let mut node = Node::<u64, u64>::with_config(0, NodeConfig { idle_strategy, ..Default::default() });
+///     node.preload(1, 10).unwrap();
+///     let handle = node.start().unwrap();
+///     assert_eq!(handle.get(1), Some(10));
+///     handle.shutdown();
+/// }
+/// ```
+///
+/// The strategy decides how long the first request after a quiet spell
+/// waits. A sleeping shard is usually mid-sleep when it arrives, a spinning
+/// one picks it up on its next pass:
+///
+/// ```
+/// use std::time::{Duration, Instant};
+/// use kv_store::{IdleStrategy, Node, NodeConfig};
+///
+/// // best of a few rounds, so a busy machine doesn't decide the result
+/// let first_after_idle = |idle_strategy| {
+///     let handle = Node::<u64, u64>::with_config(0, NodeConfig { idle_strategy, ..Default::default() }).start().unwrap();
+///     handle.get(1);
+///     let best = (0..3)
+///         .map(|_| {
+///             std::thread::sleep(Duration::from_millis(250));
+///             let start = Instant::now();
+///             handle.get(1);
+///             start.elapsed()
+///         })
+///         .min()
+///         .unwrap();
+///     handle.shutdown();
+///     best
+/// };
+///
+/// assert!(first_after_idle(IdleStrategy::Sleep(Duration::from_millis(200))) > Duration::from_millis(50));
+/// assert!(first_after_idle(IdleStrategy::NoYield) < Duration::from_millis(50));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleStrategy {
+    /// Sleep for the given time. Cheap on shared machines.
+    Sleep(std::time::Duration),
+    /// Give the rest of the time slice back to the scheduler.
+    Yield,
+    /// Spin without ever entering the scheduler. Lowest wake-up latency, but
+    /// pegs the core at 100%; only for cores set aside for the shard
+    /// (`isolcpus`), never for cores shared with other work.
+    NoYield,
+}
+
+impl Default for IdleStrategy {
+    fn default() -> Self {
+        IdleStrategy::Sleep(std::time::Duration::from_micros(1))
+    }
+}
+
+impl IdleStrategy {
+    fn idle(self) {
+        match self {
+            IdleStrategy::Sleep(duration) => thread::sleep(duration),
+            IdleStrategy::Yield => thread::yield_now(),
+            IdleStrategy::NoYield => std::hint::spin_loop(),
+        }
+    }
+}
+
+/// What an `APPLY` batch does with a key that appears in it more than once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BatchDuplicates {
+    /// Apply every op in order, so the last one for a key decides.
+    #[default]
+    LastWins,
+    /// Apply only the first op for each key; later ones fail with
+    /// `DuplicateKey` and change nothing.
+    FirstWins,
+    /// Apply nothing if any key repeats; every op fails with `DuplicateKey`.
+    Reject,
+}
+
+/// What a shard does when it can't be pinned to its core. On platforms
+/// without an affinity backend (`core_affinity::is_supported`) no shard
+/// can be: `Prefer` runs them all unpinned without warnings, and `Require`
+/// fails `start`.
+///
+/// ```
+/// use kv_store::{core_affinity, core_affinity::CoreId, AffinityPolicy, KVError, Node, NodeConfig, Request};
+///
+/// let node = |affinity_policy| {
+///     let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 2]), affinity_policy, ..Default::default() };
+///     Node::<u64, u64>::with_config(0, config)
+/// };
+///
+/// let mut prefer = node(AffinityPolicy::Prefer);
+/// let key = (0..).find(|k| prefer.route(k) == 1).unwrap();
+/// prefer.send_shard(1, Request::PUT(key, 1)).unwrap();
+/// let handle = prefer.start().unwrap();
+/// while handle.stats()[1].inserts == 0 {
+///     std::thread::yield_now();
+/// }
+/// handle.shutdown();
+///
+/// if !core_affinity::is_supported() {
+///     assert!(core_affinity::get_core_ids().is_none());
+///     assert!(matches!(node(AffinityPolicy::Require).start(), Err(KVError::AffinityFailed(_))));
+/// }
+/// ```
+///
+/// No platform can pin to a core the machine doesn't have:
+///
+/// ```
+/// use kv_store::{core_affinity::CoreId, AffinityPolicy, KVError, Node, NodeConfig};
+///
+/// let start = |affinity_policy| {
+///     let config = NodeConfig { core_map: Some(vec![CoreId { id: 1000 }]), affinity_policy, ..Default::default() };
+///     Node::<u64, u64>::with_config(0, config).start()
+/// };
+///
+/// assert!(matches!(start(AffinityPolicy::Require), Err(KVError::AffinityFailed(shards)) if shards == [0]));
+///
+/// let prefer = start(AffinityPolicy::Prefer).unwrap();
+/// assert_eq!(prefer.unpinned(), [0]);
+/// prefer.shutdown();
+///
+/// let off = start(AffinityPolicy::Off).unwrap();
+/// assert!(off.unpinned().is_empty());
+/// off.shutdown();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AffinityPolicy {
+    /// Fail `Node::run` if any shard couldn't be pinned.
+    Require,
+    /// Log the failure and keep running unpinned.
+    #[default]
+    Prefer,
+    /// Don't try to pin at all.
+    Off,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NodeConfig {
+    /// Leave one core free of shards so the thread driving the node doesn't
+    /// compete with a pinned shard. This costs one shard (never below one).
+    pub reserve_core_for_ingress : bool,
+    pub affinity_policy          : AffinityPolicy,
+    /// Which shards share a macOS affinity tag. Unused elsewhere.
+    pub affinity_tags            : core_affinity::AffinityTags,
+    /// Cores to pin shards to, one shard per entry. Defaults to `0..num_cpus`.
+    /// Take these from a `CorePool` to keep several nodes off each other's cores.
+    pub core_map                 : Option<Vec<core_affinity::CoreId>>,
+    /// Per-shard change stream capacity. `None` turns change capture off.
+    pub change_capacity          : Option<usize>,
+    /// Relative share of the key space per shard, for cores of uneven speed.
+    /// Shards without an entry weigh 1.0. Empty means uniform routing.
+    pub core_weights             : Vec<f64>,
+    /// Give each shard a bloom filter so lookups of absent keys skip the map.
+    /// The filter can only make a miss cheaper, never turn a hit into one,
+    /// and deleted keys stay deleted.
+    ///
+    /// ```
+    /// use kv_store::{bloom::BloomConfig, core_affinity::CoreId, Node, NodeConfig, Request};
+    ///
+    /// let config = NodeConfig {
+    ///     core_map: Some(vec![CoreId { id: 0 }; 2]),
+    ///     bloom_filter: Some(BloomConfig { expected_items: 64, false_positive_rate: 0.01 }),
+    ///     ..Default::default()
+    /// };
+    /// let mut node = Node::<u64, u64>::with_config(0, config);
+    /// let keys: Vec<u64> = (0..).filter(|k| node.route(k) == 1).take(100).collect();
+    /// for &k in &keys {
+    ///     node.preload(k, k).unwrap();
+    /// }
+    /// for &k in &keys[..60] {
+    ///     node.send_shard(1, Request::TAKE(k)).unwrap();
+    /// }
+    ///
+    /// let handle = node.start().unwrap();
+    /// while handle.stats()[1].deletes < 60 {
+    ///     std::thread::yield_now();
+    /// }
+    /// assert!(keys[..60].iter().all(|&k| handle.get(k).is_none()));
+    /// assert!(keys[60..].iter().all(|&k| handle.get(k) == Some(k)));
+    /// assert!((1_000_000..1_000_100).all(|k| handle.get(k).is_none()));
+    /// handle.shutdown();
+    /// ```
+    pub bloom_filter             : Option<BloomConfig>,
+    /// Rebuild preloaded shard maps on their own pinned thread, so Linux's
+    /// first-touch policy puts the table on the shard's NUMA node. Only does
+    /// anything on machines with more than one node.
+    pub numa_local_maps          : bool,
+    /// Stack size for shard threads. `None` keeps the platform default.
+    pub thread_stack_size        : Option<usize>,
+    /// Seeds the routing hash. Changing it changes which shard owns each key,
+    /// so anything laid out by the old routing (snapshots, client-side route
+    /// plans) no longer lines up. The shard maps don't see it; they hash
+    /// with `bucket_hasher`.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, Node, NodeConfig};
+    ///
+    /// let node = |hash_seed| {
+    ///     let core_map = Some(vec![CoreId { id: 0 }; 4]);
+    ///     Node::<u64, u64>::with_config(0, NodeConfig { core_map, hash_seed, ..Default::default() })
+    /// };
+    /// let (a, b) = (node(1), node(2));
+    /// assert!((0..64u64).any(|k| a.route(&k) != b.route(&k)));
+    /// ```
+    pub hash_seed                : u64,
+    pub idle_strategy            : IdleStrategy,
+    /// Hard cap on keys across all shards. Inserts of new keys past it fail
+    /// with `KVError::CapacityFull`; overwrites of existing keys still work.
+    pub max_total_keys           : Option<usize>,
+    /// How many keys of room a shard leases from the shared count at once.
+    /// Higher means less cross-shard traffic, but inserts can be rejected
+    /// up to `shards * slack` keys before the cap is really reached.
+    pub key_count_slack          : usize,
+    /// Report requests that take longer than this to serve: each one bumps
+    /// the shard's `slow_ops` counter and, with the `trace` feature, logs
+    /// its kind and duration to stderr. `None` skips the timing altogether.
+    pub slow_op_threshold        : Option<std::time::Duration>,
+    /// Serve the shards from this many threads instead of one each. Shards
+    /// are dealt round-robin to workers, which pin to their first shard's
+    /// core and take turns draining each shard's queues, so a request can
+    /// wait behind a whole pass over the worker's other shards. `None`, or
+    /// a count at or above the shard count, keeps one thread per shard.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, Node, NodeConfig, Request};
+    ///
+    /// let config = NodeConfig {
+    ///     core_map: Some(vec![CoreId { id: 0 }; 8]),
+    ///     workers: Some(2),
+    ///     ..Default::default()
+    /// };
+    /// let mut node = Node::<u64, u64>::with_config(0, config);
+    /// for dst in 1..8 {
+    ///     let key = (0..).find(|k| node.route(k) == dst).unwrap();
+    ///     node.send_shard(dst, Request::PUT(key, key)).unwrap();
+    /// }
+    ///
+    /// let handle = node.start().unwrap();
+    /// while handle.stats()[1..].iter().any(|s| s.inserts == 0) {
+    ///     std::thread::yield_now();
+    /// }
+    /// handle.shutdown();
+    /// ```
+    pub workers                  : Option<usize>,
+    /// Slots in each shard-to-shard ring buffer. `None` means
+    /// `QUEUE_CAPACITY`; zero is rejected since no request could ever fit,
+    /// and so is a size whose queues together would overflow the address
+    /// space.
+    ///
+    /// ```
+    /// use kv_store::{KVError, Node, NodeConfig};
+    ///
+    /// let config = NodeConfig { queue_capacity: Some(1 << 62), ..Default::default() };
+    /// assert!(matches!(Node::<u64, u64>::try_with_config(0, config), Err(KVError::InvalidConfig(_))));
+    /// ```
+    pub queue_capacity           : Option<usize>,
+    /// Refuse to build more shards than this, since the queue mesh grows with
+    /// the square of the shard count. `None` means `MAX_SHARDS`.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, KVError, Node, NodeConfig};
+    ///
+    /// let config = |shards, max_shards| NodeConfig {
+    ///     core_map: Some(vec![CoreId { id: 0 }; shards]),
+    ///     max_shards,
+    ///     ..Default::default()
+    /// };
+    /// let node = |config| Node::<u64, u64>::try_with_config(0, config);
+    ///
+    /// assert!(matches!(node(config(100_000, None)), Err(KVError::TooManyShards { shards: 100_000, max: 1024 })));
+    /// assert!(matches!(node(config(9, Some(8))), Err(KVError::TooManyShards { .. })));
+    /// assert!(node(config(8, Some(8))).is_ok());
+    /// ```
+    pub max_shards               : Option<usize>,
+    /// Route keys to this many partitions, spread over the shards, instead
+    /// of straight to shards. Keeps placement in whole partitions so a later
+    /// rescale can move them as units. Fewer partitions than shards leaves
+    /// some shards without keys.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, routing::Partitioner, Node, NodeConfig};
+    ///
+    /// let config = NodeConfig {
+    ///     core_map: Some(vec![CoreId { id: 0 }; 4]),
+    ///     partitions: Some(64),
+    ///     ..Default::default()
+    /// };
+    /// let a = Node::<u64, u64>::with_config(0, config.clone());
+    /// let b = Node::<u64, u64>::with_config(1, config);
+    ///
+    /// let Partitioner::Partitioned { table } = a.routing_info().partitioner else { unreachable!() };
+    /// assert_eq!(table.len(), 64);
+    /// assert!((0..4).all(|shard| table.iter().filter(|&&s| s == shard).count() == 16));
+    /// assert!((0..1_000u64).all(|k| a.route(&k) == b.route(&k)));
+    /// ```
+    pub partitions               : Option<usize>,
+    /// Buffer up to this many `PUT` requests per shard and insert them in
+    /// one go, with a single `reserve`, when the buffer fills, another kind
+    /// of request arrives, or the shard runs out of queued work. Buffered
+    /// puts are readable right away, but `len` and `keys` only count them
+    /// once flushed, and a buffered put that finds the node at
+    /// `max_total_keys` is dropped. 0 turns buffering off.
+    pub write_staging            : usize,
+    /// How `APPLY` treats a key repeated within one batch.
+    pub batch_duplicates         : BatchDuplicates,
+    /// The hash that picks each key's shard, together with `hash_seed`.
+    pub routing_hasher           : routing::RoutingHasher,
+    /// The hash each shard's map buckets keys with. Only `routing_hasher`
+    /// decides which shard a key lands on, so this can be swapped for speed
+    /// without moving any key.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, routing::{BucketHasher, RoutingHasher}, Node, NodeConfig};
+    ///
+    /// let node = |routing_hasher, bucket_hasher| {
+    ///     let core_map = Some(vec![CoreId { id: 0 }; 4]);
+    ///     let config = NodeConfig { core_map, routing_hasher, bucket_hasher, ..Default::default() };
+    ///     Node::<u64, u64>::with_config(0, config)
+    /// };
+    /// let mut fast = node(RoutingHasher::Sip, BucketHasher::Fnv);
+    /// let keyed = node(RoutingHasher::Sip, BucketHasher::Random);
+    /// let fnv = node(RoutingHasher::Fnv, BucketHasher::Fnv);
+    ///
+    /// assert!((0..1_000u64).all(|k| fast.route(&k) == keyed.route(&k)));
+    /// assert!((0..1_000u64).any(|k| fast.route(&k) != fnv.route(&k)));
+    /// assert_eq!(fast.routing_info().hasher, RoutingHasher::Sip);
+    ///
+    /// let mut per_shard = [0; 4];
+    /// for k in 0..1_000u64 {
+    ///     per_shard[fast.route(&k)] += 1;
+    ///     assert_eq!(fast.preload(k, k).unwrap(), None);
+    /// }
+    /// assert!(per_shard.iter().all(|&n| n > 150));
+    /// assert_eq!(fast.preload(7, 0).unwrap(), Some(7));
+    /// ```
+    pub bucket_hasher            : routing::BucketHasher,
+}
+
+impl NodeConfig {
+    /// Rejects settings that would build a node that can't work, such as a
+    /// zero-slot queue or a core id past what affinity masks can hold.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::{CoreId, CORE_ID_LIMIT}, KVError, Node, NodeConfig};
+    ///
+    /// let config = NodeConfig { queue_capacity: Some(0), ..Default::default() };
+    /// assert!(matches!(config.validate(), Err(KVError::InvalidConfig(_))));
+    /// assert!(Node::<u64, u64>::try_with_config(0, config).is_err());
+    /// assert!(NodeConfig { queue_capacity: Some(1), ..Default::default() }.validate().is_ok());
+    ///
+    /// if CORE_ID_LIMIT < usize::MAX {
+    ///     let config = NodeConfig { core_map: Some(vec![CoreId { id: CORE_ID_LIMIT }]), ..Default::default() };
+    ///     assert!(matches!(config.validate(), Err(KVError::InvalidConfig(_))));
+    /// }
+    /// ```
+    pub fn validate(&self) -> KVResult<()> {
+        if self.queue_capacity == Some(0) {
+            return Err(KVError::InvalidConfig("queue_capacity must be at least 1"));
+        }
+        if self.change_capacity == Some(0) {
+            return Err(KVError::InvalidConfig("change_capacity must be at least 1"));
+        }
+        if self.partitions == Some(0) {
+            return Err(KVError::InvalidConfig("partitions must be at least 1"));
+        }
+        if self.workers == Some(0) {
+            return Err(KVError::InvalidConfig("workers must be at least 1"));
+        }
+        if self.core_map.iter().flatten().any(|core| core.id >= core_affinity::CORE_ID_LIMIT) {
+            return Err(KVError::InvalidConfig("core_map ids must be below core_affinity::CORE_ID_LIMIT"));
+        }
+        Ok(())
+    }
+
+    fn queue_slots(&self) -> usize {
+        self.queue_capacity.unwrap_or(QUEUE_CAPACITY)
+    }
+
+    // bytes of request queues for `num_shards` shards: `n * (n - 1)` links
+    // plus `n` ingress queues. `None` if that's more than can be allocated
+    fn queue_bytes<K, V>(&self, num_shards: usize) -> Option<usize> {
+        let bytes = num_shards
+            .checked_mul(num_shards)?
+            .checked_mul(self.queue_slots())?
+            .checked_mul(std::mem::size_of::<Request<K, V>>())?;
+        (bytes <= isize::MAX as usize).then_some(bytes)
+    }
+}
+
+#[allow(non_camel_case_types)]
 pub enum Request<K, V> {
     PUT(K, V),
     GET(K),
+    TAKE(K),
+    DELETE_IF { key: K, expected: V },
+    PUT_IF_ABSENT { key: K, value: V },
+    APPLY_FN { key: K, fn_name: String, arg: V },
+    GET_PROJECTED { key: K, projector: String },
+    /// A `GET` whose result, cloned, is sent back on `reply`.
+    GET_REPLY { key: K, reply: mpsc::Sender<Option<V>> },
+    /// A `TAKE` whose result is sent back on `reply`.
+    TAKE_REPLY { key: K, reply: mpsc::Sender<Option<V>> },
+    /// A `PUT_IF_ABSENT` whose outcome is sent back on `reply`.
+    PUT_IF_ABSENT_REPLY { key: K, value: V, reply: mpsc::Sender<KVResult<OpOutcome>> },
+    RETAIN(fn(&K, &V) -> bool),
+    COPY { from: K, to: K },
+    /// A `COPY` whose outcome is sent back on `reply`.
+    COPY_REPLY { from: K, to: K, reply: mpsc::Sender<KVResult<OpOutcome>> },
+    /// Reads every key in one step and sends the values back on `reply`;
+    /// `CrossShard` if any key lives on another shard.
+    MULTI_GET_ATOMIC { keys: Vec<K>, reply: mpsc::Sender<KVResult<Vec<Option<V>>>> },
+    APPLY(Vec<WriteOp<K, V>>),
+    #[cfg(feature = "metadata")]
+    GET_META(K),
+}
+
+impl<K, V> Request<K, V> {
+    // the keys the receiving shard must own; COPY's target may live elsewhere
+    #[cfg(debug_assertions)]
+    fn owned_keys(&self) -> Vec<&K> {
+        match self {
+            Request::PUT(key, _)
+            | Request::GET(key)
+            | Request::TAKE(key)
+            | Request::DELETE_IF { key, .. }
+            | Request::PUT_IF_ABSENT { key, .. }
+            | Request::APPLY_FN { key, .. }
+            | Request::GET_PROJECTED { key, .. }
+            | Request::GET_REPLY { key, .. }
+            | Request::TAKE_REPLY { key, .. }
+            | Request::PUT_IF_ABSENT_REPLY { key, .. }
+            | Request::COPY { from: key, .. }
+            | Request::COPY_REPLY { from: key, .. } => vec![key],
+            #[cfg(feature = "metadata")]
+            Request::GET_META(key) => vec![key],
+            // batches check their own keys and fail with CrossShard
+            Request::MULTI_GET_ATOMIC { .. } | Request::APPLY(_) => Vec::new(),
+            Request::RETAIN(_) => Vec::new(),
+        }
+    }
+
+    fn is_write(&self) -> bool {
+        match self {
+            Request::GET(_)
+            | Request::GET_PROJECTED { .. }
+            | Request::GET_REPLY { .. }
+            | Request::MULTI_GET_ATOMIC { .. } => false,
+            #[cfg(feature = "metadata")]
+            Request::GET_META(_) => false,
+            _ => true,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Request::PUT(..) => "PUT",
+            Request::GET(_) => "GET",
+            Request::TAKE(_) => "TAKE",
+            Request::DELETE_IF { .. } => "DELETE_IF",
+            Request::PUT_IF_ABSENT { .. } => "PUT_IF_ABSENT",
+            Request::APPLY_FN { .. } => "APPLY_FN",
+            Request::GET_PROJECTED { .. } => "GET_PROJECTED",
+            Request::GET_REPLY { .. } => "GET_REPLY",
+            Request::TAKE_REPLY { .. } => "TAKE_REPLY",
+            Request::PUT_IF_ABSENT_REPLY { .. } => "PUT_IF_ABSENT_REPLY",
+            Request::RETAIN(_) => "RETAIN",
+            Request::COPY { .. } => "COPY",
+            Request::COPY_REPLY { .. } => "COPY_REPLY",
+            Request::MULTI_GET_ATOMIC { .. } => "MULTI_GET_ATOMIC",
+            Request::APPLY(_) => "APPLY",
+            #[cfg(feature = "metadata")]
+            Request::GET_META(_) => "GET_META",
+        }
+    }
+}
+
+impl Request<(), ()> {
+    // one of each variant, so the names are only spelled out in `kind`;
+    // a new variant goes here too
+    fn kinds() -> Vec<&'static str> {
+        let samples = [
+            Request::PUT((), ()),
+            Request::GET(()),
+            Request::TAKE(()),
+            Request::DELETE_IF { key: (), expected: () },
+            Request::PUT_IF_ABSENT { key: (), value: () },
+            Request::APPLY_FN { key: (), fn_name: String::new(), arg: () },
+            Request::GET_PROJECTED { key: (), projector: String::new() },
+            Request::GET_REPLY { key: (), reply: mpsc::channel().0 },
+            Request::TAKE_REPLY { key: (), reply: mpsc::channel().0 },
+            Request::PUT_IF_ABSENT_REPLY { key: (), value: (), reply: mpsc::channel().0 },
+            Request::RETAIN(|_, _| true),
+            Request::COPY { from: (), to: () },
+            Request::COPY_REPLY { from: (), to: (), reply: mpsc::channel().0 },
+            Request::MULTI_GET_ATOMIC { keys: Vec::new(), reply: mpsc::channel().0 },
+            Request::APPLY(Vec::new()),
+            #[cfg(feature = "metadata")]
+            Request::GET_META(()),
+        ];
+        samples.iter().map(Request::kind).collect()
+    }
+}
+
+/// What this build of the crate supports, for clients that have to check
+/// before relying on an optional feature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Built with `metadata`: `EntryMeta` and `GET_META`.
+    pub metadata  : bool,
+    /// Built with `checksums`: values are verified on read.
+    pub checksums : bool,
+    /// Every `Request` variant this build accepts, by name.
+    pub requests  : Vec<&'static str>,
+}
+
+impl Capabilities {
+    fn current() -> Self {
+        Capabilities {
+            metadata: cfg!(feature = "metadata"),
+            checksums: cfg!(feature = "checksums"),
+            requests: Request::kinds(),
+        }
+    }
+}
+
+/// Receives a shard's id and its map each time the shard rotates.
+pub type ArchiveFn<K, V> = Arc<dyn Fn(usize, HashMap<K, V>) + Send + Sync>;
+
+struct Rotation<K, V> {
+    every   : std::time::Duration,
+    next    : Option<std::time::Instant>,
+    archive : ArchiveFn<K, V>,
+}
+
+/// A registered read-modify-write step: `(current, arg) -> new`.
+pub type ApplyFn<V> = fn(&V, &V) -> V;
+
+/// A registered read-side view of a value, e.g. one with its large fields
+/// left out.
+pub type ProjectFn<V> = fn(&V) -> V;
+
+/// What a conditional operation did, as opposed to whether it ran at all.
+///
+/// ```
+/// use kv_store::{LocalNode, OpOutcome};
+///
+/// let mut node = LocalNode::<&str, u64>::new();
+/// node.put("k", 1);
+///
+/// assert_eq!(node.shard().delete_if(&"k", &2), OpOutcome::ConditionFailed);
+/// assert_eq!(node.shard().delete_if(&"k", &1), OpOutcome::Applied);
+/// assert_eq!(node.shard().delete_if(&"k", &1), OpOutcome::NotFound);
+/// assert_eq!(node.copy(&"k", "other").unwrap(), OpOutcome::NotFound);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpOutcome {
+    /// The condition held and the mutation happened.
+    Applied,
+    /// The key exists but the condition didn't hold; nothing changed.
+    ConditionFailed,
+    /// The key doesn't exist; nothing changed.
+    NotFound,
+    /// The write was queued for the shard owning its target and lands once
+    /// that shard serves it.
+    Forwarded,
+}
+
+/// One write in an `APPLY` batch.
+pub enum WriteOp<K, V> {
+    Put(K, V),
+    Delete(K),
+}
+
+impl<K, V> WriteOp<K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            WriteOp::Put(key, _) | WriteOp::Delete(key) => key,
+        }
+    }
+}
+
+/// A bitwise update for flag values, see `Shard::bit_op`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+}
+
+impl BitOp {
+    fn apply(self, current: u64, operand: u64) -> u64 {
+        match self {
+            BitOp::And => current & operand,
+            BitOp::Or => current | operand,
+            BitOp::Xor => current ^ operand,
+        }
+    }
+}
+
+/// Bookkeeping kept next to each value when the `metadata` feature is on.
+#[cfg(feature = "metadata")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryMeta {
+    pub created  : std::time::Instant,
+    pub modified : std::time::Instant,
+    /// Number of writes to the key since it was created, starting at 1.
+    pub version  : u64,
+}
+
+#[cfg(feature = "metadata")]
+impl EntryMeta {
+    fn new() -> Self {
+        let now = std::time::Instant::now();
+        EntryMeta { created: now, modified: now, version: 1 }
+    }
+
+    fn touch(&mut self) {
+        self.modified = std::time::Instant::now();
+        self.version += 1;
+    }
+}
+
+struct Entry<V> {
+    value    : V,
+    #[cfg(feature = "metadata")]
+    meta     : EntryMeta,
+    #[cfg(feature = "checksums")]
+    checksum : u64,
+}
+
+impl<V: Hash> Entry<V> {
+    fn new(value: V) -> Self {
+        Entry {
+            #[cfg(feature = "checksums")]
+            checksum: routing::hash_key(&value),
+            value,
+            #[cfg(feature = "metadata")]
+            meta: EntryMeta::new(),
+        }
+    }
+
+    // after an in-place edit through ValueMut
+    fn restamp(&mut self) {
+        #[cfg(feature = "metadata")]
+        self.meta.touch();
+        #[cfg(feature = "checksums")]
+        {
+            self.checksum = routing::hash_key(&self.value);
+        }
+    }
+
+    fn replace(&mut self, value: V) -> V {
+        #[cfg(feature = "metadata")]
+        self.meta.touch();
+        #[cfg(feature = "checksums")]
+        {
+            self.checksum = routing::hash_key(&value);
+        }
+        std::mem::replace(&mut self.value, value)
+    }
+}
+
+/// Mutable access to a stored value. Entry bookkeeping (metadata,
+/// checksum) is refreshed when the guard is dropped.
+pub struct ValueMut<'a, V: Hash> {
+    entry: &'a mut Entry<V>,
+}
+
+impl<V: Hash> std::ops::Deref for ValueMut<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        &self.entry.value
+    }
+}
+
+impl<V: Hash> std::ops::DerefMut for ValueMut<'_, V> {
+    fn deref_mut(&mut self) -> &mut V {
+        &mut self.entry.value
+    }
+}
+
+impl<V: Hash> Drop for ValueMut<'_, V> {
+    fn drop(&mut self) {
+        self.entry.restamp();
+    }
 }
 
 pub struct Shard<K, V> {
-    id      : usize,
-    data    : HashMap<K, V>,
-    in_vec  : Vec<Option<Consumer<Request<K, V>>> >,
-    out_vec : Vec<Option<Producer<Request<K, V>>> >, 
+    id        : usize,
+    core      : core_affinity::CoreId,
+    data      : HashMap<K, Entry<V>, routing::BucketState>,
+    in_vec    : Vec<Option<Consumer<Request<K, V>>> >,
+    out_vec   : Vec<Option<Producer<Request<K, V>>> >, 
+    changes   : Option<ChangeProducer>,
+    bloom     : Option<BloomFilter>,
+    idle      : IdleStrategy,
+    budget    : Option<KeyBudget>,
+    fns       : HashMap<String, ApplyFn<V>>,
+    views     : HashMap<String, ProjectFn<V>>,
+    stats     : Arc<ShardStats>,
+    router    : Router,
+    slow_op   : Option<std::time::Duration>,
+    // keys to read once on the shard's own core, before it reports ready
+    warm      : Vec<K>,
+    dups      : BatchDuplicates,
+    rotation  : Option<Rotation<K, V>>,
+    read_only : Arc<AtomicBool>,
+    staged    : Vec<(K, V)>,
+    stage_max : usize,
 }
 
 impl<K, V> Shard<K, V>
@@ -52,67 +827,776 @@ where
     K: Key,
     V: Value
 {
-    fn new(id: usize, num_cores: usize) -> Self {
+    fn new(id: usize, core: core_affinity::CoreId, num_cores: usize) -> Self {
         Shard {
             id,
-            data: HashMap::new(),
+            core,
+            data: HashMap::default(),
             out_vec: (0..num_cores).map(|_| None).collect(),
             in_vec: (0..num_cores).map(|_| None).collect(),
+            changes: None,
+            bloom: None,
+            idle: IdleStrategy::default(),
+            budget: None,
+            fns: HashMap::new(),
+            views: HashMap::new(),
+            stats: Arc::default(),
+            router: Router::new(1, &[], 0, None),
+            slow_op: None,
+            warm: Vec::new(),
+            dups: BatchDuplicates::default(),
+            rotation: None,
+            read_only: Arc::default(),
+            staged: Vec::new(),
+            stage_max: 0,
+        }
+    }
+
+    fn record_change(&mut self, key: &K, kind: ChangeKind) {
+        if let Some(changes) = &mut self.changes {
+            changes.record(self.id, routing::hash_key(key), kind);
+        }
+    }
+
+    fn pin(&self, policy: AffinityPolicy, tags: core_affinity::AffinityTags) -> bool {
+        if policy == AffinityPolicy::Off {
+            return true;
+        }
+        // nothing to try; Prefer runs unpinned quietly, Require still fails
+        if !core_affinity::is_supported() {
+            return false;
+        }
+
+        #[cfg(target_os = "macos")]
+        let pinned = core_affinity::set_tag_for_current(tags.tag(self.core));
+        #[cfg(not(target_os = "macos"))]
+        let pinned = {
+            let _ = tags;
+            // a cgroup or another thread can still narrow or widen the mask
+            core_affinity::set_for_current(self.core)
+                && core_affinity::current_affinity().is_none_or(|cores| cores == [self.core])
+        };
+        if !pinned && policy == AffinityPolicy::Prefer {
+            eprintln!("shard {} could not be pinned, running unpinned", self.id);
         }
+        pinned
     }
 
-    fn run(mut self) {
-        core_affinity::set_for_current(self.id.into());
-        loop {           
-            let mut requests = Vec::new();
+    // moves the table into memory first touched by the calling thread
+    fn relocate(&mut self) {
+        let mut data = HashMap::with_capacity_and_hasher(self.data.len(), self.data.hasher().clone());
+        data.extend(self.data.drain());
+        self.data = data;
+    }
+
+    // serves everything queued so far, taking one request per queue in turn
+    // so a busy sender can't hold the others back; false if there was nothing
+    fn poll(&mut self) -> bool {
+        let rounds = self.in_vec.iter().flatten().map(Consumer::slots).max().unwrap_or(0);
+        let mut requests = Vec::new();
+        for _ in 0..rounds {
             for consumer in self.in_vec.iter_mut().flatten() {
-                while let Ok(request) = consumer.pop() {
+                if let Ok(request) = consumer.pop() {
                     requests.push(request);
                 }
             }
-            
-            for request in requests {
-                let _ = self.handle_request(request);
-            }
-            
-            std::thread::sleep(std::time::Duration::from_micros(1));
         }
+
+        let busy = !requests.is_empty();
+        for request in requests {
+            let _ = self.handle_request(request);
+        }
+        self.flush_staged();
+        busy
     }
 
     pub fn handle_request(&mut self, request: Request<K, V>) -> KVResult<()> {
+        #[cfg(debug_assertions)]
+        self.check_ownership(&request);
+
+        if request.is_write() && self.read_only.load(Ordering::Acquire) {
+            return Err(KVError::ReadOnly);
+        }
+
+        let request = match request {
+            Request::PUT(key, value) if self.stage_max > 0 => {
+                self.staged.push((key, value));
+                if self.staged.len() >= self.stage_max {
+                    self.flush_staged();
+                }
+                return Ok(());
+            },
+            request => request,
+        };
+        self.flush_staged();
+
+        let Some(threshold) = self.slow_op else {
+            return self.serve(request);
+        };
+        #[cfg(feature = "trace")]
+        let kind = request.kind();
+        let start = std::time::Instant::now();
+        let result = self.serve(request);
+        let took = start.elapsed();
+        if took > threshold {
+            self.stats.slow_op();
+            #[cfg(feature = "trace")]
+            eprintln!("shard {}: slow {kind} took {took:?}", self.id);
+        }
+        result
+    }
+
+    fn owns(&self, key: &K) -> bool {
+        self.router.route(key) == self.id
+    }
+
+    // a misrouted key means some sender's routing no longer matches the node's
+    #[cfg(debug_assertions)]
+    fn check_ownership(&self, request: &Request<K, V>) {
+        for key in request.owned_keys() {
+            let owner = self.router.route(key);
+            assert_eq!(owner, self.id, "shard {} got a {} for a key owned by shard {owner}", self.id, request.kind());
+        }
+    }
+
+    /// Makes write requests fail with `ReadOnly` while reads keep working.
+    /// Only requests are refused; direct calls like `insert` still write.
+    ///
+    /// ```
+    /// use kv_store::{KVError, LocalNode, Request};
+    ///
+    /// let mut node = LocalNode::<&str, u64>::new();
+    /// node.put("k", 1);
+    /// node.shard().set_read_only(true);
+    ///
+    /// assert!(matches!(node.shard().handle_request(Request::PUT("k", 2)), Err(KVError::ReadOnly)));
+    /// assert!(node.shard().handle_request(Request::GET("k")).is_ok());
+    /// assert_eq!(node.get(&"k"), Some(&1));
+    ///
+    /// node.shard().set_read_only(false);
+    /// node.shard().handle_request(Request::PUT("k", 2)).unwrap();
+    /// assert_eq!(node.get(&"k"), Some(&2));
+    /// ```
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Release);
+    }
+
+    /// Inserts every `PUT` held back by `NodeConfig::write_staging`.
+    ///
+    /// ```
+    /// use kv_store::{LocalNode, Request};
+    ///
+    /// let mut node = LocalNode::<u64, u64>::new();
+    /// node.shard().set_write_staging(8);
+    /// for k in 0..3 {
+    ///     node.shard().handle_request(Request::PUT(k, k * 10)).unwrap();
+    /// }
+    ///
+    /// assert_eq!(node.get(&2), Some(&20));
+    /// assert_eq!(node.len(), 0);
+    /// node.shard().flush_staged();
+    /// assert_eq!((node.len(), node.get(&2)), (3, Some(&20)));
+    /// ```
+    pub fn flush_staged(&mut self) {
+        if self.staged.is_empty() {
+            return;
+        }
+        let staged = std::mem::take(&mut self.staged);
+        self.data.reserve(staged.len());
+        for (key, value) in staged {
+            let _ = self.try_insert(key, value);
+        }
+    }
+
+    /// Sets `NodeConfig::write_staging` for this shard alone.
+    pub fn set_write_staging(&mut self, max: usize) {
+        self.flush_staged();
+        self.stage_max = max;
+    }
+
+    /// Sets `NodeConfig::slow_op_threshold` for this shard alone.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use kv_store::{LocalNode, Request};
+    ///
+    /// let mut node = LocalNode::<&str, u64>::new();
+    /// node.register_fn("slow", |current, _| {
+    ///     std::thread::sleep(Duration::from_millis(20));
+    ///     *current
+    /// });
+    /// node.put("k", 1);
+    /// node.shard().set_slow_op_threshold(Some(Duration::from_millis(5)));
+    ///
+    /// node.shard().handle_request(Request::GET("k")).unwrap();
+    /// assert_eq!(node.stats().slow_ops, 0);
+    ///
+    /// let slow = Request::APPLY_FN { key: "k", fn_name: "slow".into(), arg: 0 };
+    /// node.shard().handle_request(slow).unwrap();
+    /// assert_eq!(node.stats().slow_ops, 1);
+    /// ```
+    pub fn set_slow_op_threshold(&mut self, threshold: Option<std::time::Duration>) {
+        self.slow_op = threshold;
+    }
+
+    fn serve(&mut self, request: Request<K, V>) -> KVResult<()> {
         match request {
             Request::PUT(key, value) => {
-                let _ = self.insert(key, value);
+                self.try_insert(key, value)?;
                 Ok(())
             },
+            #[cfg(not(feature = "checksums"))]
             Request::GET(key) => {
                 let _ = self.get(&key);
                 Ok(())
             },
+            #[cfg(feature = "checksums")]
+            Request::GET(key) => {
+                self.get_checked(&key)?;
+                Ok(())
+            },
+            Request::TAKE(key) => {
+                let _ = self.take(&key);
+                Ok(())
+            },
+            Request::DELETE_IF { key, expected } => {
+                let _ = self.delete_if(&key, &expected);
+                Ok(())
+            },
+            Request::PUT_IF_ABSENT { key, value } => {
+                self.put_if_absent(key, value)?;
+                Ok(())
+            },
+            Request::APPLY_FN { key, fn_name, arg } => {
+                self.apply_fn(&key, &fn_name, &arg)?;
+                Ok(())
+            },
+            Request::GET_PROJECTED { key, projector } => {
+                self.get_projected(&key, &projector)?;
+                Ok(())
+            },
+            // the caller may have given up waiting; that's not the shard's problem
+            #[cfg(not(feature = "checksums"))]
+            Request::GET_REPLY { key, reply } => {
+                reply.send(self.get(&key).cloned()).ok();
+                Ok(())
+            },
+            // on a checksum failure the reply is dropped unsent
+            #[cfg(feature = "checksums")]
+            Request::GET_REPLY { key, reply } => {
+                reply.send(self.get_checked(&key)?.cloned()).ok();
+                Ok(())
+            },
+            Request::TAKE_REPLY { key, reply } => {
+                reply.send(self.take(&key)).ok();
+                Ok(())
+            },
+            Request::PUT_IF_ABSENT_REPLY { key, value, reply } => {
+                reply.send(self.put_if_absent(key, value)).ok();
+                Ok(())
+            },
+            Request::RETAIN(keep) => {
+                let _ = self.retain(keep);
+                Ok(())
+            },
+            Request::COPY { from, to } => {
+                self.copy(&from, to)?;
+                Ok(())
+            },
+            Request::COPY_REPLY { from, to, reply } => {
+                reply.send(self.copy(&from, to)).ok();
+                Ok(())
+            },
+            Request::MULTI_GET_ATOMIC { keys, reply } => {
+                let values = self.multi_get(&keys).map(|values| values.into_iter().map(Option::<&V>::cloned).collect());
+                reply.send(values).ok();
+                Ok(())
+            },
+            Request::APPLY(ops) => {
+                let _ = self.apply(ops);
+                Ok(())
+            },
+            #[cfg(feature = "metadata")]
+            Request::GET_META(key) => {
+                let _ = self.get_meta(&key);
+                Ok(())
+            },
         }
     }
 
+    /// Pushes onto the queue towards shard `dst`. A shard has no queue to
+    /// itself, and ids past the mesh have none either; both are `NoRoute`.
     pub fn send(&mut self, dst: usize, request: Request<K, V>) -> KVResult<()> {
-        if let Some(queue) = &mut self.out_vec[dst] {
-            queue.push(request).map_err(|_r| KVError::Unknown)
-        } else {
-            Err(KVError::Unknown)
-        }
+        let src = self.id;
+        let queue = self.out_vec
+            .get_mut(dst)
+            .and_then(Option::as_mut)
+            .ok_or(KVError::NoRoute { src, dst })?;
+        queue.push(request).map_err(|_r| KVError::QueueFull { dst })
     }
 
+    /// Stores `value`, counting a new key against `max_total_keys` even if
+    /// that goes over it. Request paths use `try_insert` instead.
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-        self.data.insert(key, value)
+        self.flush_staged();
+        if let Some(budget) = &mut self.budget
+            && !self.data.contains_key(&key)
+        {
+            budget.force();
+        }
+        self.store(key, value)
+    }
+
+    /// Stores `value`, failing with `CapacityFull` if `key` is new and the
+    /// node is at `max_total_keys`.
+    pub fn try_insert(&mut self, key: K, value: V) -> KVResult<Option<V>> {
+        self.flush_staged();
+        if let Some(budget) = &mut self.budget
+            && !self.data.contains_key(&key)
+            && !budget.claim()
+        {
+            return Err(KVError::CapacityFull);
+        }
+        Ok(self.store(key, value))
+    }
+
+    fn store(&mut self, key: K, value: V) -> Option<V> {
+        self.record_change(&key, ChangeKind::Put);
+        if let Some(bloom) = &mut self.bloom {
+            bloom.insert(&key);
+        }
+        match self.data.entry(key) {
+            hash_map::Entry::Occupied(mut occupied) => {
+                self.stats.overwrite();
+                Some(occupied.get_mut().replace(value))
+            },
+            hash_map::Entry::Vacant(vacant) => {
+                self.stats.insert();
+                vacant.insert(Entry::new(value));
+                None
+            },
+        }
     }
 
     pub fn get(&self, key: &K) -> Option<&V> {
+        let value = self.lookup(key);
+        self.stats.read(value.is_some());
+        value
+    }
+
+    // get without counting, for ops that read on their way to something else
+    fn lookup(&self, key: &K) -> Option<&V> {
+        if let Some(value) = self.staged_value(key) {
+            return Some(value);
+        }
+        self.stored(key).map(|entry| &entry.value)
+    }
+
+    // the map's entry for `key`, not probing the map if the bloom filter
+    // rules it out
+    fn stored(&self, key: &K) -> Option<&Entry<V>> {
+        if self.bloom.as_ref().is_some_and(|bloom| !bloom.may_contain(key)) {
+            return None;
+        }
         self.data.get(key)
     }
+
+    // the newest buffered put for `key`, if any
+    fn staged_value(&self, key: &K) -> Option<&V> {
+        self.staged.iter().rev().find(|(staged, _)| staged == key).map(|(_, value)| value)
+    }
+
+    /// `get`, but fails with `DataCorruption` if the value no longer matches
+    /// the checksum taken when it was written. Staged puts haven't been
+    /// checksummed yet and are returned as they are.
+    ///
+    /// A value whose hash can change behind the map's back shows up as
+    /// corrupt:
+    ///
+    /// ```
+    /// use std::{hash::{Hash, Hasher}, sync::{atomic::{AtomicU64, Ordering}, Arc}};
+    /// use kv_store::{KVError, LocalNode};
+    ///
+    /// #[derive(Clone, Debug)]
+    /// struct Cell(Arc<AtomicU64>);
+    ///
+    /// impl Hash for Cell {
+    ///     fn hash<H: Hasher>(&self, state: &mut H) {
+    ///         self.0.load(Ordering::Relaxed).hash(state);
+    ///     }
+    /// }
+    /// impl PartialEq for Cell {
+    ///     fn eq(&self, other: &Self) -> bool {
+    ///         Arc::ptr_eq(&self.0, &other.0)
+    ///     }
+    /// }
+    /// impl Eq for Cell {}
+    ///
+    /// let cell = Cell(Arc::new(AtomicU64::new(1)));
+    /// let mut node = LocalNode::<&str, Cell>::new();
+    /// node.put("k", cell.clone());
+    /// assert!(node.shard().get_checked(&"k").unwrap().is_some());
+    ///
+    /// cell.0.store(2, Ordering::Relaxed);
+    /// assert!(matches!(node.shard().get_checked(&"k"), Err(KVError::DataCorruption { .. })));
+    /// assert!(node.shard().get_checked(&"missing").unwrap().is_none());
+    /// assert_eq!((node.stats().hits, node.stats().misses), (2, 1));
+    /// ```
+    #[cfg(feature = "checksums")]
+    pub fn get_checked(&self, key: &K) -> KVResult<Option<&V>> {
+        if let Some(value) = self.staged_value(key) {
+            self.stats.read(true);
+            return Ok(Some(value));
+        }
+        let entry = self.stored(key);
+        self.stats.read(entry.is_some());
+        let Some(entry) = entry else {
+            return Ok(None);
+        };
+        if routing::hash_key(&entry.value) != entry.checksum {
+            return Err(KVError::DataCorruption { key_hash: routing::hash_key(key) });
+        }
+        Ok(Some(&entry.value))
+    }
+
+    /// Every key on the shard, without touching the values.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.data.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Reads several keys in one step, so no write lands between the reads.
+    /// Fails with `CrossShard`, reading nothing, if any key lives on another
+    /// shard.
+    pub fn multi_get(&self, keys: &[K]) -> KVResult<Vec<Option<&V>>> {
+        if !keys.iter().all(|key| self.owns(key)) {
+            return Err(KVError::CrossShard);
+        }
+        Ok(keys.iter().map(|key| self.get(key)).collect())
+    }
+
+    /// Creation/modification stamps for `key`, without touching the value.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use kv_store::LocalNode;
+    ///
+    /// let mut node = LocalNode::<&str, u64>::new();
+    /// node.put("k", 1);
+    /// let first = node.shard().get_meta(&"k").unwrap();
+    /// std::thread::sleep(Duration::from_millis(1));
+    /// node.put("k", 2);
+    /// let second = node.shard().get_meta(&"k").unwrap();
+    ///
+    /// assert_eq!(second.created, first.created);
+    /// assert!(second.modified > first.modified);
+    /// assert_eq!((first.version, second.version), (1, 2));
+    /// assert_eq!(node.shard().get_meta(&"missing"), None);
+    /// ```
+    #[cfg(feature = "metadata")]
+    pub fn get_meta(&self, key: &K) -> Option<EntryMeta> {
+        self.data.get(key).map(|entry| entry.meta)
+    }
+
+    /// In-place access to the value at `key`; counts as a write.
+    pub fn get_mut(&mut self, key: &K) -> Option<ValueMut<'_, V>> {
+        self.flush_staged();
+        if !self.data.contains_key(key) {
+            return None;
+        }
+        self.record_change(key, ChangeKind::Put);
+        self.data.get_mut(key).map(|entry| ValueMut { entry })
+    }
+
+    /// Removes `key` and hands back its value. Requests on a shard run one at
+    /// a time, so of several racing takes for one key only the first gets `Some`.
+    pub fn take(&mut self, key: &K) -> Option<V> {
+        self.flush_staged();
+        let taken = self.data.remove(key).map(|entry| entry.value);
+        if taken.is_none() {
+            self.stats.delete_absent();
+        } else {
+            self.removed(key);
+        }
+        taken
+    }
+
+    /// Keeps only the entries `keep` returns `true` for, in one shard-local
+    /// pass, and returns how many were removed.
+    pub fn retain(&mut self, keep: impl Fn(&K, &V) -> bool) -> usize {
+        self.flush_staged();
+        let removed: Vec<K> = self.data
+            .extract_if(|key, entry| !keep(key, &entry.value))
+            .map(|(key, _)| key)
+            .collect();
+        for key in &removed {
+            self.removed(key);
+        }
+        removed.len()
+    }
+
+    /// Swaps the whole map for an empty one and returns the old contents.
+    /// Much cheaper than deleting keys one by one: no change records are
+    /// emitted and the delete counters don't move.
+    ///
+    /// ```
+    /// use kv_store::LocalNode;
+    ///
+    /// let mut node = LocalNode::<&str, u64>::new();
+    /// node.put("12:00", 41);
+    /// node.put("12:01", 7);
+    ///
+    /// let old = node.shard().rotate();
+    /// assert_eq!(old.get("12:01"), Some(&7));
+    /// assert!(node.is_empty());
+    /// ```
+    pub fn rotate(&mut self) -> HashMap<K, V> {
+        self.flush_staged();
+        let fresh = HashMap::with_hasher(self.data.hasher().clone());
+        let old = std::mem::replace(&mut self.data, fresh);
+        if let Some(budget) = &mut self.budget {
+            for _ in 0..old.len() {
+                budget.release();
+            }
+        }
+        if let Some(bloom) = &mut self.bloom {
+            bloom.rebuild(self.data.keys());
+        }
+        old.into_iter().map(|(key, entry)| (key, entry.value)).collect()
+    }
+
+    // rotates once the interval set by Node::rotate_every is up
+    fn tick(&mut self) {
+        let Some(rotation) = &mut self.rotation else {
+            return;
+        };
+        let now = std::time::Instant::now();
+        let next = *rotation.next.get_or_insert(now + rotation.every);
+        if now < next {
+            return;
+        }
+        rotation.next = Some(now + rotation.every);
+        let archive = Arc::clone(&rotation.archive);
+        let old = self.rotate();
+        archive(self.id, old);
+    }
+
+    // bookkeeping for a key that just left the map
+    fn removed(&mut self, key: &K) {
+        self.stats.delete();
+        self.record_change(key, ChangeKind::Delete);
+        if let Some(budget) = &mut self.budget {
+            budget.release();
+        }
+        if let Some(bloom) = &mut self.bloom
+            && bloom.note_delete()
+        {
+            bloom.rebuild(self.data.keys());
+        }
+    }
+
+    /// Applies a batch of puts and deletes in order as one step; nothing else
+    /// runs on the shard in between. Returns each op's previous value, or the
+    /// error that stopped that op alone. Repeated keys are handled as
+    /// `NodeConfig::batch_duplicates` says, and a batch with any key owned by
+    /// another shard is refused whole: every op fails with `CrossShard`.
+    ///
+    /// ```
+    /// use kv_store::{BatchDuplicates, KVError, LocalNode, WriteOp};
+    ///
+    /// let load = |policy| {
+    ///     let mut node = LocalNode::<&str, u32>::new();
+    ///     node.shard().set_batch_duplicates(policy);
+    ///     let results = node.shard().apply(vec![WriteOp::Put("a", 1), WriteOp::Put("b", 2), WriteOp::Put("a", 3)]);
+    ///     let failed = results.iter().filter(|r| matches!(r, Err(KVError::DuplicateKey))).count();
+    ///     (node.get(&"a").copied(), node.len(), failed)
+    /// };
+    ///
+    /// assert_eq!(load(BatchDuplicates::LastWins), (Some(3), 2, 0));
+    /// assert_eq!(load(BatchDuplicates::FirstWins), (Some(1), 2, 1));
+    /// assert_eq!(load(BatchDuplicates::Reject), (None, 0, 3));
+    /// ```
+    pub fn apply(&mut self, ops: Vec<WriteOp<K, V>>) -> Vec<KVResult<Option<V>>> {
+        if ops.iter().any(|op| !self.owns(op.key())) {
+            return ops.iter().map(|_| Err(KVError::CrossShard)).collect();
+        }
+
+        let repeats: Vec<bool> = match self.dups {
+            BatchDuplicates::LastWins => vec![false; ops.len()],
+            _ => {
+                let mut seen = std::collections::HashSet::with_capacity(ops.len());
+                ops.iter().map(|op| !seen.insert(op.key())).collect()
+            },
+        };
+        if self.dups == BatchDuplicates::Reject && repeats.contains(&true) {
+            return ops.iter().map(|_| Err(KVError::DuplicateKey)).collect();
+        }
+
+        ops.into_iter()
+            .zip(repeats)
+            .map(|(op, repeat)| match op {
+                _ if repeat => Err(KVError::DuplicateKey),
+                WriteOp::Put(key, value) => self.try_insert(key, value),
+                WriteOp::Delete(key) => Ok(self.take(&key)),
+            })
+            .collect()
+    }
+
+    /// Sets `NodeConfig::batch_duplicates` for this shard alone.
+    pub fn set_batch_duplicates(&mut self, policy: BatchDuplicates) {
+        self.dups = policy;
+    }
+
+    /// Replaces the value at `key` with the registered function `fn_name`
+    /// applied to it and `arg`, in one shard-local step.
+    pub fn apply_fn(&mut self, key: &K, fn_name: &str, arg: &V) -> KVResult<OpOutcome> {
+        let f = *self.fns
+            .get(fn_name)
+            .ok_or_else(|| KVError::UnknownFunction(fn_name.to_string()))?;
+        match self.get_mut(key) {
+            Some(mut value) => {
+                *value = f(&value, arg);
+                Ok(OpOutcome::Applied)
+            },
+            None => Ok(OpOutcome::NotFound),
+        }
+    }
+
+    /// The value at `key` passed through the registered projector
+    /// `projector`, so only the projection leaves the shard.
+    pub fn get_projected(&self, key: &K, projector: &str) -> KVResult<Option<V>> {
+        let project = self.views
+            .get(projector)
+            .ok_or_else(|| KVError::UnknownFunction(projector.to_string()))?;
+        #[cfg(feature = "checksums")]
+        let value = self.get_checked(key)?;
+        #[cfg(not(feature = "checksums"))]
+        let value = self.get(key);
+        Ok(value.map(project))
+    }
+
+    /// Inserts only if `key` is missing; an existing value is left alone and
+    /// reported as `ConditionFailed`. Of several racing inserts for one key
+    /// exactly one is `Applied`.
+    ///
+    /// ```
+    /// use kv_store::{LocalNode, OpOutcome, Request};
+    ///
+    /// let mut node = LocalNode::<&str, u64>::new();
+    /// assert_eq!(node.shard().put_if_absent("k", 1).unwrap(), OpOutcome::Applied);
+    /// assert_eq!(node.shard().put_if_absent("k", 2).unwrap(), OpOutcome::ConditionFailed);
+    /// assert_eq!(node.get(&"k"), Some(&1));
+    ///
+    /// node.shard().handle_request(Request::PUT_IF_ABSENT { key: "k", value: 3 }).unwrap();
+    /// assert_eq!(node.get(&"k"), Some(&1));
+    /// assert_eq!(node.stats().condition_failures, 2);
+    /// ```
+    pub fn put_if_absent(&mut self, key: K, value: V) -> KVResult<OpOutcome> {
+        self.flush_staged();
+        if self.data.contains_key(&key) {
+            self.stats.condition_failed();
+            return Ok(OpOutcome::ConditionFailed);
+        }
+        self.try_insert(key, value)?;
+        Ok(OpOutcome::Applied)
+    }
+
+    /// Copies the value at `from` into `to`, leaving `from` as it is. If `to`
+    /// lives on this shard that's one step and `Applied`; otherwise the copy
+    /// goes to its owner as a `PUT` and is reported as `Forwarded`, since it
+    /// hasn't landed yet.
+    pub fn copy(&mut self, from: &K, to: K) -> KVResult<OpOutcome> {
+        let Some(value) = self.get(from).cloned() else {
+            return Ok(OpOutcome::NotFound);
+        };
+        let dst = self.router.route(&to);
+        if dst == self.id {
+            self.try_insert(to, value)?;
+        } else {
+            self.send(dst, Request::PUT(to, value))?;
+            return Ok(OpOutcome::Forwarded);
+        }
+        Ok(OpOutcome::Applied)
+    }
+
+    /// Removes `key` only if it currently holds `expected`, e.g. to release a
+    /// lock only while still holding it.
+    ///
+    /// ```
+    /// use kv_store::{LocalNode, Request};
+    ///
+    /// let mut node = LocalNode::<&str, u64>::new();
+    /// node.put("lock", 7);
+    ///
+    /// node.shard().handle_request(Request::DELETE_IF { key: "lock", expected: 8 }).unwrap();
+    /// assert_eq!(node.get(&"lock"), Some(&7));
+    ///
+    /// node.shard().handle_request(Request::DELETE_IF { key: "lock", expected: 7 }).unwrap();
+    /// assert_eq!(node.get(&"lock"), None);
+    ///
+    /// node.shard().handle_request(Request::DELETE_IF { key: "lock", expected: 7 }).unwrap();
+    /// assert_eq!(node.stats().deletes_absent, 1);
+    /// ```
+    pub fn delete_if(&mut self, key: &K, expected: &V) -> OpOutcome {
+        match self.lookup(key) {
+            None => {
+                self.stats.delete_absent();
+                OpOutcome::NotFound
+            },
+            Some(current) if current != expected => {
+                self.stats.condition_failed();
+                OpOutcome::ConditionFailed
+            },
+            Some(_) => {
+                self.take(key);
+                OpOutcome::Applied
+            },
+        }
+    }
+}
+
+impl<K: Key> Shard<K, u64> {
+    /// Applies `op` with `operand` to the flags at `key` and returns the new
+    /// flags. A missing key counts as 0, so `Or` on it sets exactly `operand`.
+    pub fn bit_op(&mut self, key: K, op: BitOp, operand: u64) -> KVResult<u64> {
+        let flags = op.apply(self.lookup(&key).copied().unwrap_or(0), operand);
+        self.try_insert(key, flags)?;
+        Ok(flags)
+    }
+
+    /// Decrements the count at `key`, removing the key once it reaches 0.
+    /// Returns the new count, so `Some(0)` means the key is gone; a missing
+    /// key is left missing and gives `None`.
+    pub fn decr_del(&mut self, key: &K) -> Option<u64> {
+        if *self.lookup(key)? <= 1 {
+            self.take(key);
+            return Some(0);
+        }
+        let mut count = self.get_mut(key)?;
+        *count -= 1;
+        Some(*count)
+    }
 }
 
 pub struct Node<K: Key, V: Value> {
-    id        : usize,
-    num_cores : usize,
-    shards    : Vec<Shard<K, V>>,
+    id           : usize,
+    num_cores    : usize,
+    config       : NodeConfig,
+    ingress_core : Option<core_affinity::CoreId>,
+    shards       : Vec<Shard<K, V>>,
+    router       : Router,
+    changes      : Option<ChangeConsumer>,
+    stats        : Vec<Arc<ShardStats>>,
+    read_only    : Arc<AtomicBool>,
+    // one queue into each shard for `NodeHandle::get`
+    ingress      : Vec<Producer<Request<K, V>>>,
 }
 
 impl<K: Key, V: Value> std::fmt::Debug for Node<K, V> {
@@ -120,54 +1604,1448 @@ impl<K: Key, V: Value> std::fmt::Debug for Node<K, V> {
         f.debug_struct("Node")
             .field("id", &self.id)
             .field("num_cores", &self.num_cores)
+            .field("ingress_core", &self.ingress_core)
             .field("active_shards", &self.shards.len())
             .finish()
     }
 }
 
+impl<V: Value> Node<u64, V> {
+    /// Splits `keys` by owner: for each shard, the runs of consecutive keys
+    /// it owns, in order. Every key in `keys` is in exactly one run. Routing
+    /// hashes keys, so neighbours rarely share a shard and the runs are
+    /// short; this is for handing each shard's keys to its own worker, not
+    /// for range scans. When weights or the partition table leave a single
+    /// shard owning the whole hash space, the range is one run found in
+    /// O(shards); otherwise every key is routed, O(range).
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, Node, NodeConfig};
+    ///
+    /// let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 4]), ..Default::default() };
+    /// let node = Node::<u64, u64>::with_config(0, config);
+    ///
+    /// let parts = node.key_partitions(100..1_100);
+    /// assert_eq!(parts.len(), 4);
+    ///
+    /// let mut seen: Vec<u64> = Vec::new();
+    /// for (shard, runs) in &parts {
+    ///     for run in runs {
+    ///         assert!(run.clone().all(|k| node.route(&k) == *shard));
+    ///         seen.extend(run.clone());
+    ///     }
+    /// }
+    /// seen.sort_unstable();
+    /// assert_eq!(seen, (100..1_100).collect::<Vec<u64>>());
+    ///
+    /// // only shard 3 has any weight, so even the whole key space is instant
+    /// let config = NodeConfig {
+    ///     core_map: Some(vec![CoreId { id: 0 }; 4]),
+    ///     core_weights: vec![0.0, 0.0, 0.0, 1.0],
+    ///     ..Default::default()
+    /// };
+    /// let node = Node::<u64, u64>::with_config(0, config);
+    /// let parts = node.key_partitions(0..u64::MAX);
+    /// assert_eq!(parts[3].1, [0..u64::MAX]);
+    /// assert!(parts[..3].iter().all(|(_, runs)| runs.is_empty()));
+    /// ```
+    pub fn key_partitions(&self, keys: std::ops::Range<u64>) -> Vec<(usize, Vec<std::ops::Range<u64>>)> {
+        let mut parts: Vec<(usize, Vec<std::ops::Range<u64>>)> =
+            (0..self.shards.len()).map(|shard| (shard, Vec::new())).collect();
+        if let [owner] = self.router.owners()[..] {
+            if !keys.is_empty() {
+                parts[owner].1.push(keys);
+            }
+            return parts;
+        }
+        let mut run_start = keys.start;
+        let mut run_shard: Option<usize> = None;
+        for key in keys.clone() {
+            let shard = self.router.route(&key);
+            if let Some(prev) = run_shard
+                && prev != shard
+            {
+                parts[prev].1.push(run_start..key);
+                run_start = key;
+            }
+            run_shard = Some(shard);
+        }
+        if let Some(last) = run_shard {
+            parts[last].1.push(run_start..keys.end);
+        }
+        parts
+    }
+}
+
 impl<K, V> Node<K, V>
 where
     K: Key,
     V: Value
 {
     pub fn new(id: usize) -> Self { // maybe something later like max cores in cluster idk
+        Self::with_config(id, NodeConfig::default())
+    }
+
+    /// A node on `ceil(cores * fraction)` cores, leaving the rest free for
+    /// other work. Always at least one shard, and never more than the cores.
+    ///
+    /// ```
+    /// use kv_store::{num_cores::num_cpus, Node};
+    ///
+    /// num_cpus::set_override(Some(8));
+    /// let shards = |fraction| Node::<u64, u64>::with_core_fraction(0, fraction).num_shards();
+    ///
+    /// assert_eq!(shards(0.0), 1);
+    /// assert_eq!(shards(0.3), 3);
+    /// assert_eq!(shards(1.0), 8);
+    /// assert_eq!(shards(4.0), 8);
+    /// assert_eq!(shards(f64::NAN), 1);
+    /// ```
+    pub fn with_core_fraction(id: usize, fraction: f64) -> Self {
+        let num_cores = num_cpus::detect();
+        let wanted = (num_cores as f64 * fraction).ceil();
+        let num_shards = if wanted.is_nan() { 1 } else { (wanted as usize).clamp(1, num_cores.max(1)) };
+
+        let config = NodeConfig {
+            core_map: Some((0..num_shards).map(Into::into).collect()),
+            ..Default::default()
+        };
+        Self::with_config(id, config)
+    }
+
+    /// A node with enough shards for `target_ops_per_sec` at
+    /// `SHARD_OPS_PER_SEC` each, clamped to `1..=cores`. The estimate
+    /// assumes small keys and values and one pinned core per shard; large
+    /// values, hot keys or a busy machine mean fewer ops per shard than that.
+    ///
+    /// ```
+    /// use kv_store::{num_cores::num_cpus, Node};
+    ///
+    /// num_cpus::set_override(Some(8));
+    /// let shards = |target| Node::<u64, u64>::for_throughput(0, target).placement().len();
+    ///
+    /// assert_eq!(shards(0), 1);
+    /// assert!(shards(10_000_000) >= shards(1_000_000));
+    /// assert_eq!(shards(u64::MAX), 8);
+    /// ```
+    pub fn for_throughput(id: usize, target_ops_per_sec: u64) -> Self {
+        let num_cores = num_cpus::detect();
+        let wanted = target_ops_per_sec.div_ceil(SHARD_OPS_PER_SEC);
+        let num_shards = usize::try_from(wanted).unwrap_or(usize::MAX).clamp(1, num_cores.max(1));
+
+        let config = NodeConfig {
+            core_map: Some((0..num_shards).map(Into::into).collect()),
+            ..Default::default()
+        };
+        Self::with_config(id, config)
+    }
+
+    /// # Panics
+    ///
+    /// If `config` fails `NodeConfig::validate` or asks for more than
+    /// `max_shards` shards; `try_with_config` returns the error instead.
+    pub fn with_config(id: usize, config: NodeConfig) -> Self {
+        Self::try_with_config(id, config).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    pub fn try_with_config(id: usize, config: NodeConfig) -> KVResult<Self> {
+        config.validate()?;
         let num_cores = num_cpus::detect();
-        
-        let mut shards: Vec<Shard<K, V>> = (0..num_cores)
-            .map(|i| Shard::new(i, num_cores))
+
+        let mut cores = config.core_map
+            .clone()
+            .unwrap_or_else(|| (0..num_cores).map(Into::into).collect());
+
+        // a broken affinity mask can report no cores at all; run one shard on
+        // core 0 rather than a node that can't route anything
+        if cores.is_empty() {
+            cores.push(0.into());
+        }
+
+        // the reserved core is always the last one so shards keep the low ids
+        let ingress_core = if config.reserve_core_for_ingress && cores.len() > 1 {
+            cores.pop()
+        } else {
+            None
+        };
+
+        let num_shards = cores.len();
+        let max = config.max_shards.unwrap_or(MAX_SHARDS);
+        if num_shards > max {
+            return Err(KVError::TooManyShards { shards: num_shards, max });
+        }
+        if config.queue_bytes::<K, V>(num_shards).is_none() {
+            return Err(KVError::InvalidConfig("queue_capacity is too large to allocate"));
+        }
+        let mut shards: Vec<Shard<K, V>> = cores
+            .into_iter()
+            .enumerate()
+            .map(|(i, core)| Shard::new(i, core, num_shards))
             .collect();
 
-        for src in 0..num_cores {
-            for dst in 0..num_cores {
+        for src in 0..num_shards {
+            for dst in 0..num_shards {
                 if src == dst {
                     continue
                 }
-                let (prod, cons) = RingBuffer::<Request<K, V>>::new(100);
+                let (prod, cons) = RingBuffer::<Request<K, V>>::new(config.queue_slots());
                 shards[src].out_vec[dst] = Some(prod);
                 shards[dst].in_vec[src]  = Some(cons);
             }
         }
 
-        Self {
+        let ingress = shards
+            .iter_mut()
+            .map(|shard| {
+                let (prod, cons) = RingBuffer::new(config.queue_slots());
+                shard.in_vec.push(Some(cons));
+                prod
+            })
+            .collect();
+
+        let router = Router::new(num_shards, &config.core_weights, config.hash_seed, config.partitions)
+            .with_hasher(config.routing_hasher);
+
+        let key_count = Arc::new(AtomicUsize::new(0));
+        for shard in &mut shards {
+            shard.router = router.clone();
+            shard.data = HashMap::with_hasher(config.bucket_hasher.state());
+            shard.idle = config.idle_strategy;
+            shard.slow_op = config.slow_op_threshold;
+            shard.dups = config.batch_duplicates;
+            shard.stage_max = config.write_staging;
+            shard.bloom = config.bloom_filter.map(BloomFilter::new);
+            shard.budget = config.max_total_keys
+                .map(|max| KeyBudget::new(Arc::clone(&key_count), max, config.key_count_slack));
+        }
+
+        let changes = config.change_capacity.map(|capacity| {
+            let dropped = Arc::new(AtomicU64::new(0));
+            let queues = shards
+                .iter_mut()
+                .map(|shard| {
+                    let (prod, cons) = RingBuffer::new(capacity);
+                    shard.changes = Some(ChangeProducer::new(prod, Arc::clone(&dropped)));
+                    cons
+                })
+                .collect();
+            ChangeConsumer::new(queues, dropped)
+        });
+
+        let stats = shards.iter().map(|shard| Arc::clone(&shard.stats)).collect();
+
+        let read_only = Arc::new(AtomicBool::new(false));
+        for shard in &mut shards {
+            shard.read_only = Arc::clone(&read_only);
+        }
+
+        Ok(Self {
             id,
             num_cores,
+            config,
+            ingress_core,
             shards,
+            router,
+            changes,
+            stats,
+            read_only,
+            ingress,
+        })
+    }
+
+    /// The shard that owns `key`.
+    ///
+    /// A node always has at least one shard, even if core detection came up
+    /// empty:
+    ///
+    /// ```
+    /// use kv_store::{num_cores::num_cpus, Node};
+    ///
+    /// num_cpus::set_override(Some(0));
+    /// let node = Node::<u64, u64>::new(0);
+    /// assert!(format!("{node:?}").contains("active_shards: 1"));
+    /// assert_eq!(node.route(&42), 0);
+    /// ```
+    ///
+    /// With weights, shards own a share of the keys proportional to theirs:
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, Node, NodeConfig};
+    ///
+    /// let config = NodeConfig {
+    ///     core_map: Some(vec![CoreId { id: 0 }; 2]),
+    ///     core_weights: vec![2.0, 1.0],
+    ///     ..Default::default()
+    /// };
+    /// let node = Node::<u64, u64>::with_config(0, config);
+    ///
+    /// let on_first = (0..30_000u64).filter(|k| node.route(k) == 0).count();
+    /// assert!((19_000..21_000).contains(&on_first));
+    /// ```
+    ///
+    /// Routing doesn't depend on the process, so two separately built nodes
+    /// with the same shape agree on every key:
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, Node, NodeConfig};
+    ///
+    /// let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 8]), ..Default::default() };
+    /// let a = Node::<u64, u64>::with_config(0, config.clone());
+    /// let b = Node::<u64, u64>::with_config(1, config);
+    ///
+    /// assert!((0..1_000u64).all(|k| a.route(&k) == b.route(&k)));
+    /// assert_eq!(a.route(&42), 4);
+    /// ```
+    pub fn route(&self, key: &K) -> usize {
+        self.router.route(key)
+    }
+
+    /// Bytes reserved by the request ring buffers.
+    ///
+    /// Every slot is as big as the largest `Request` variant, which carries a
+    /// `V` inline, and there are `queue_capacity` slots on each of the
+    /// `n * (n - 1)` shard-to-shard links plus the `n` ingress queues that
+    /// `send_shard` and `NodeHandle` feed. Small values stay inline with no allocation, but
+    /// for large values store a `Box<T>` (or `Arc<T>`) instead. That keeps
+    /// the slots pointer-sized and costs one allocation per write.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, Node, NodeConfig};
+    ///
+    /// let config = || NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 4]), ..Default::default() };
+    ///
+    /// let inline = Node::<u64, [u8; 4096]>::with_config(0, config()).queue_memory();
+    /// let boxed = Node::<u64, Box<[u8; 4096]>>::with_config(0, config()).queue_memory();
+    /// assert!(boxed * 50 < inline);
+    /// ```
+    pub fn queue_memory(&self) -> usize {
+        self.config.queue_bytes::<K, V>(self.shards.len()).unwrap_or(usize::MAX)
+    }
+
+    /// A snapshot of how this node routes keys.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, routing::Partitioner, Node, NodeConfig};
+    ///
+    /// let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 3]), hash_seed: 7, ..Default::default() };
+    /// let info = Node::<u64, u64>::with_config(0, config).routing_info();
+    ///
+    /// assert_eq!((info.num_shards, info.seed), (3, 7));
+    /// assert_eq!(info.partitioner, Partitioner::Modulo);
+    /// ```
+    pub fn routing_info(&self) -> routing::RoutingInfo {
+        self.router.info()
+    }
+
+    /// The single shard owning every key in `keys`, for requests that must run
+    /// as one shard-local step.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, KVError, Node, NodeConfig};
+    ///
+    /// let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 2]), ..Default::default() };
+    /// let node = Node::<u64, u64>::with_config(0, config);
+    ///
+    /// let other = (1..).find(|k| node.route(k) != node.route(&0)).unwrap();
+    /// assert_eq!(node.route_all(&[0, 0]).unwrap(), node.route(&0));
+    /// assert!(matches!(node.route_all(&[0, other]), Err(KVError::CrossShard)));
+    /// ```
+    pub fn route_all(&self, keys: &[K]) -> KVResult<usize> {
+        self.route_keys(keys.iter())
+    }
+
+    /// Routes `keys` once for reuse across several batches over them.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, Node, NodeConfig, WriteOp};
+    ///
+    /// let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 4]), ..Default::default() };
+    /// let node = Node::<u64, u64>::with_config(0, config);
+    /// let keys: Vec<u64> = (0..32).collect();
+    /// let plan = node.plan_routes(&keys);
+    /// assert!((0..plan.len()).all(|i| plan.shard(i) == node.route(&keys[i])));
+    ///
+    /// // one MULTI_GET_ATOMIC and one APPLY of deletes per shard
+    /// let gets = plan.split(keys.iter().copied());
+    /// let deletes = plan.split(keys.iter().map(|&k| WriteOp::<u64, u64>::Delete(k)));
+    /// for (shard, (gets, deletes)) in gets.iter().zip(&deletes).enumerate() {
+    ///     assert_eq!(gets.len(), deletes.len());
+    ///     if !gets.is_empty() {
+    ///         assert_eq!(node.route_all(gets).unwrap(), shard);
+    ///         assert_eq!(node.route_ops(deletes).unwrap(), shard);
+    ///     }
+    /// }
+    /// ```
+    pub fn plan_routes(&self, keys: &[K]) -> routing::RoutePlan {
+        self.router.plan(keys)
+    }
+
+    /// `route_all` for the keys of an `APPLY` batch.
+    pub fn route_ops(&self, ops: &[WriteOp<K, V>]) -> KVResult<usize> {
+        self.route_keys(ops.iter().map(WriteOp::key))
+    }
+
+    fn route_keys<'a>(&self, mut keys: impl Iterator<Item = &'a K>) -> KVResult<usize> {
+        let shard = self.route(keys.next().ok_or(KVError::EmptyBatch)?);
+        if keys.all(|key| self.route(key) == shard) {
+            Ok(shard)
+        } else {
+            Err(KVError::CrossShard)
         }
     }
 
-    pub fn run(self) {
-        let handles: Vec<JoinHandle<()>> = self.shards
-            .into_iter()
-            .map(|shard| thread::spawn(move || shard.run()))
+    /// The change stream, when `change_capacity` is set. Can only be taken
+    /// once; take it before `run` and drain it from another thread.
+    ///
+    /// ```
+    /// use kv_store::{cdc::ChangeKind, core_affinity::CoreId, Node, NodeConfig, Request};
+    ///
+    /// let config = NodeConfig {
+    ///     core_map: Some(vec![CoreId { id: 0 }; 2]),
+    ///     change_capacity: Some(16),
+    ///     ..Default::default()
+    /// };
+    /// let mut node = Node::<u64, u64>::with_config(0, config);
+    /// let mut changes = node.change_stream().unwrap();
+    ///
+    /// node.send_shard(1, Request::PUT(7, 70)).unwrap();
+    /// std::thread::spawn(move || node.run());
+    ///
+    /// let record = loop {
+    ///     if let Some(record) = changes.try_recv() { break record; }
+    /// };
+    /// assert_eq!((record.shard, record.kind, record.version), (1, ChangeKind::Put, 1));
+    /// ```
+    pub fn change_stream(&mut self) -> Option<ChangeConsumer> {
+        self.changes.take()
+    }
+
+    /// The core left free of shards when `reserve_core_for_ingress` is set.
+    ///
+    /// ```
+    /// use kv_store::{Node, NodeConfig};
+    ///
+    /// let config = NodeConfig { reserve_core_for_ingress: true, ..Default::default() };
+    /// let node = Node::<u64, u64>::with_config(0, config);
+    ///
+    /// if kv_store::num_cores::num_cpus::detect() > 1 {
+    ///     assert!(node.ingress_core().is_some());
+    ///     assert!(node.pin_ingress());
+    /// }
+    /// ```
+    pub fn ingress_core(&self) -> Option<core_affinity::CoreId> {
+        self.ingress_core
+    }
+
+    /// Pins the calling thread to the reserved ingress core. Returns `false`
+    /// when no core was reserved or pinning failed.
+    pub fn pin_ingress(&self) -> bool {
+        match self.ingress_core {
+            Some(core) => core_affinity::set_for_current(core),
+            None => false,
+        }
+    }
+
+    /// How many shards the node runs, after detection, fractions and any
+    /// reserved ingress core are accounted for.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, Node, NodeConfig};
+    ///
+    /// let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 3]), ..Default::default() };
+    /// let node = Node::<u64, u64>::with_config(0, config);
+    /// assert_eq!(node.num_shards(), 3);
+    ///
+    /// let handle = node.start().unwrap();
+    /// assert_eq!(handle.num_shards(), 3);
+    /// handle.shutdown();
+    /// ```
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Where each shard will be pinned, indexed by shard id.
+    pub fn placement(&self) -> Vec<core_affinity::CoreId> {
+        self.shards.iter().map(|shard| shard.core).collect()
+    }
+
+    /// Writes straight into the owning shard's map, bypassing the queues.
+    /// Only possible before `start`, while the node still owns its shards.
+    ///
+    /// ```
+    /// use kv_store::{KVError, Node, NodeConfig};
+    ///
+    /// let config = NodeConfig { max_total_keys: Some(2), ..Default::default() };
+    /// let mut node = Node::<u64, u64>::with_config(0, config);
+    ///
+    /// node.preload(1, 10).unwrap();
+    /// node.preload(2, 20).unwrap();
+    /// assert!(matches!(node.preload(3, 30), Err(KVError::CapacityFull)));
+    /// assert_eq!(node.preload(1, 11).unwrap(), Some(10));
+    /// ```
+    pub fn preload(&mut self, key: K, value: V) -> KVResult<Option<V>> {
+        let shard = self.route(&key);
+        self.shards[shard].try_insert(key, value)
+    }
+
+    /// Spawns and pins a thread per shard and returns once every shard has
+    /// tried to pin. Under `AffinityPolicy::Require` no shard starts
+    /// serving unless every shard pinned; otherwise the threads are torn down
+    /// and the unpinned shard ids are returned.
+    ///
+    /// Before `start` the node can be inspected (`route`, `placement`),
+    /// preloaded, and have its change stream taken; requests queued with
+    /// `send_shard` are served once it starts. Afterwards only the returned
+    /// `NodeHandle` is left.
+    ///
+    /// ```
+    /// use kv_store::{num_cores::num_cpus, Node};
+    ///
+    /// num_cpus::set_override(Some(2));
+    /// let mut node = Node::<u64, u64>::new(0);
+    /// assert_eq!(node.placement().len(), 2);
+    ///
+    /// assert_eq!(node.preload(1, 10).unwrap(), None);
+    /// assert_eq!(node.preload(1, 11).unwrap(), Some(10));
+    ///
+    /// let handle = node.start().unwrap();
+    /// assert_eq!(handle.id(), 0);
+    /// ```
+    ///
+    /// Shard threads get the platform stack size unless `thread_stack_size`
+    /// is set:
+    ///
+    /// ```
+    /// use kv_store::{Node, NodeConfig};
+    ///
+    /// let config = NodeConfig { thread_stack_size: Some(4 << 20), ..Default::default() };
+    /// assert!(Node::<u64, u64>::with_config(0, config).start().is_ok());
+    /// ```
+    pub fn start(self) -> KVResult<NodeHandle<K, V>> {
+        let policy = self.config.affinity_policy;
+        let tags = self.config.affinity_tags;
+        let num_shards = self.shards.len();
+        let relocate = self.config.numa_local_maps
+            && policy != AffinityPolicy::Off
+            && numa::node_count() > 1;
+
+        let (pinned_tx, pinned_rx) = mpsc::channel();
+        let gate = Arc::new(StartGate::default());
+        let ready = Arc::new(Readiness::default());
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let num_threads = self.config.workers.map_or(num_shards, |workers| workers.clamp(1, num_shards));
+        let mut groups: Vec<Vec<Shard<K, V>>> = (0..num_threads).map(|_| Vec::new()).collect();
+        for (i, shard) in self.shards.into_iter().enumerate() {
+            groups[i % num_threads].push(shard);
+        }
+
+        let mut handles: Vec<JoinHandle<()>> = Vec::with_capacity(num_threads);
+        let mut spawned_shards = 0;
+        let mut spawn_error = None;
+        for (worker, mut group) in groups.into_iter().enumerate() {
+            let pinned_tx = pinned_tx.clone();
+            let gate = Arc::clone(&gate);
+            let ready = Arc::clone(&ready);
+            let stop = Arc::clone(&stop);
+
+            let name = match group.as_slice() {
+                [shard] => format!("shard-{}", shard.id),
+                _ => format!("worker-{worker}"),
+            };
+            let mut builder = thread::Builder::new().name(name);
+            if let Some(size) = self.config.thread_stack_size {
+                builder = builder.stack_size(size);
+            }
+
+            let group_len = group.len();
+            let spawned = builder.spawn(move || {
+                let pinned = group[0].pin(policy, tags);
+                for shard in &mut group {
+                    if pinned && relocate {
+                        shard.relocate();
+                    }
+                    pinned_tx.send((shard.id, pinned)).ok();
+                }
+                // let go before parking, so start sees the channel close once
+                // every thread has either reported or died
+                drop(pinned_tx);
+                if gate.wait() {
+                    for shard in &mut group {
+                        for key in std::mem::take(&mut shard.warm) {
+                            let _ = shard.get(&key);
+                        }
+                    }
+                    ready.signal();
+                    run_shards(&mut group, &stop);
+                }
+            });
+
+            match spawned {
+                Ok(handle) => {
+                    handles.push(handle);
+                    spawned_shards += group_len;
+                },
+                Err(e) => {
+                    spawn_error = Some(e);
+                    break;
+                },
+            }
+        }
+
+        // only the shard threads hold senders now, so a thread that dies
+        // before reporting ends the iteration instead of blocking it
+        drop(pinned_tx);
+        let reports: Vec<(usize, bool)> = pinned_rx.iter().take(spawned_shards).collect();
+        let mut unpinned: Vec<usize> = reports
+            .iter()
+            .filter_map(|&(id, pinned)| (!pinned).then_some(id))
             .collect();
+        unpinned.sort_unstable();
+
+        let error = match spawn_error {
+            Some(e) => Some(KVError::Spawn(e)),
+            None if reports.len() < spawned_shards => Some(KVError::ShardDied),
+            None if policy == AffinityPolicy::Require && !unpinned.is_empty() => {
+                Some(KVError::AffinityFailed(unpinned.clone()))
+            },
+            None => None,
+        };
+        gate.open(error.is_none());
+
+        let handle = NodeHandle {
+            id: self.id,
+            handles,
+            ready,
+            stop,
+            stats: self.stats,
+            read_only: self.read_only,
+            hooks: Mutex::default(),
+            router: self.router,
+            ingress: self.ingress.into_iter().map(Mutex::new).collect(),
+            unpinned,
+        };
+        match error {
+            // dropping stops and joins the threads without re-raising a panic
+            Some(e) => {
+                drop(handle);
+                Err(e)
+            },
+            None => Ok(handle),
+        }
+    }
+
+    /// Queues reads of `keys` that each shard runs on its own core once it
+    /// starts, before it counts as ready, so its hot entries are already in
+    /// cache when traffic arrives. Best effort: values aren't returned, and
+    /// missing keys just count as misses.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, Node, NodeConfig};
+    ///
+    /// let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 2]), ..Default::default() };
+    /// let mut node = Node::<u64, u64>::with_config(0, config);
+    /// for k in 0..3 {
+    ///     node.preload(k, k).unwrap();
+    /// }
+    /// node.warmup(0..4);
+    ///
+    /// let handle = node.start().unwrap();
+    /// handle.wait_ready();
+    /// let stats = handle.stats();
+    /// assert_eq!(stats.iter().map(|s| s.reads()).sum::<u64>(), 4);
+    /// assert_eq!(stats.iter().map(|s| s.hits).sum::<u64>(), 3);
+    /// handle.shutdown();
+    /// ```
+    pub fn warmup(&mut self, keys: impl IntoIterator<Item = K>) {
+        for key in keys {
+            let shard = self.router.route(&key);
+            self.shards[shard].warm.push(key);
+        }
+    }
 
-        for handle in handles {
-            handle.join().ok();
+    /// Makes every shard `rotate` once per `every` while running, handing the
+    /// old map to `archive` on the shard's thread. Each shard swaps its own
+    /// map in one step, but shards rotate on their own clocks, not together.
+    ///
+    /// ```
+    /// use std::{sync::{mpsc, Arc, Mutex}, time::Duration};
+    /// use kv_store::{core_affinity::CoreId, Node, NodeConfig};
+    ///
+    /// let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }]), ..Default::default() };
+    /// let mut node = Node::<u64, u64>::with_config(0, config);
+    /// node.preload(1, 10).unwrap();
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let tx = Mutex::new(tx);
+    /// node.rotate_every(Duration::from_millis(1), Arc::new(move |shard, old| {
+    ///     tx.lock().unwrap().send((shard, old)).ok();
+    /// }));
+    ///
+    /// let handle = node.start().unwrap();
+    /// let archived: Vec<_> = rx.iter().take(2).collect();
+    /// assert_eq!(archived[0].1.get(&1), Some(&10));
+    /// assert!(archived[1].1.is_empty());
+    /// handle.shutdown();
+    /// ```
+    pub fn rotate_every(&mut self, every: std::time::Duration, archive: ArchiveFn<K, V>) {
+        for shard in &mut self.shards {
+            shard.rotation = Some(Rotation { every, next: None, archive: Arc::clone(&archive) });
         }
     }
 
+    /// `start`, then block on the shard threads.
+    pub fn run(self) -> KVResult<()> {
+        self.start().map(NodeHandle::join)
+    }
+
+    /// Operation counters per shard, indexed by shard id.
+    pub fn stats(&self) -> Vec<StatsSnapshot> {
+        self.stats.iter().map(|stats| stats.snapshot()).collect()
+    }
+
+    /// Registers `f` under `name` on every shard, for `Request::APPLY_FN`.
+    /// Functions cross the queues by name, never as closures, so they must
+    /// be registered before `start`. Re-registering a name replaces it.
+    pub fn register_fn(&mut self, name: &str, f: ApplyFn<V>) {
+        for shard in &mut self.shards {
+            shard.fns.insert(name.to_string(), f);
+        }
+    }
+
+    /// Registers `project` under `name` on every shard, for
+    /// `Request::GET_PROJECTED`. Same rules as `register_fn`.
+    pub fn register_projection(&mut self, name: &str, project: ProjectFn<V>) {
+        for shard in &mut self.shards {
+            shard.views.insert(name.to_string(), project);
+        }
+    }
+
+    /// Queues `req` on shard `shard_id`'s ingress queue, served once the
+    /// node starts.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, KVError, Node, NodeConfig, Request};
+    ///
+    /// let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }]), ..Default::default() };
+    /// let mut node = Node::<u64, u64>::with_config(0, config);
+    ///
+    /// assert!(node.send_shard(0, Request::PUT(1, 1)).is_ok());
+    /// assert!(matches!(node.send_shard(9, Request::PUT(1, 1)), Err(KVError::UnknownShard(9))));
+    ///
+    /// let handle = node.start().unwrap();
+    /// assert_eq!(handle.get(1), Some(1));
+    /// handle.shutdown();
+    /// ```
     pub fn send_shard(&mut self, shard_id: usize, req: Request<K, V>) -> Result<(), KVError> {
-        self.shards[0].send(shard_id, req) // abuse shard 0 out vec to reach the other shards todo: maybe fix this is kinda shitty
+        let queue = self.ingress.get_mut(shard_id).ok_or(KVError::UnknownShard(shard_id))?;
+        queue.push(req).map_err(|_r| KVError::QueueFull { dst: shard_id })
+    }
+}
+
+// a shard thread's loop, or a worker's over several shards
+fn run_shards<K: Key, V: Value>(shards: &mut [Shard<K, V>], stop: &AtomicBool) {
+    loop {
+        // one last pass after the stop flag so queued requests still land
+        let stopping = stop.load(Ordering::Acquire);
+
+        let mut busy = false;
+        for shard in shards.iter_mut() {
+            busy |= shard.poll();
+            shard.tick();
+        }
+
+        if stopping {
+            break;
+        }
+
+        if !busy {
+            shards[0].idle.idle();
+        }
+    }
+}
+
+// holds shard threads between pinning and serving until start() decides
+#[derive(Default)]
+struct StartGate {
+    state : Mutex<Option<bool>>,
+    cond  : Condvar,
+}
+
+impl StartGate {
+    fn open(&self, serve: bool) {
+        *self.state.lock().unwrap_or_else(|e| e.into_inner()) = Some(serve);
+        self.cond.notify_all();
+    }
+
+    fn wait(&self) -> bool {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let state = self.cond
+            .wait_while(state, |state| state.is_none())
+            .unwrap_or_else(|e| e.into_inner());
+        state.unwrap_or(false)
+    }
+}
+
+// counts shards that made it past the gate into their loop
+#[derive(Default)]
+struct Readiness {
+    count : Mutex<usize>,
+    cond  : Condvar,
+}
+
+impl Readiness {
+    fn signal(&self) {
+        *self.count.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+        self.cond.notify_all();
+    }
+
+    fn get(&self) -> usize {
+        *self.count.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn wait_for(&self, n: usize) {
+        let count = self.count.lock().unwrap_or_else(|e| e.into_inner());
+        drop(self.cond.wait_while(count, |count| *count < n));
+    }
+}
+
+/// A single shard driven directly from the calling thread: no queues, no
+/// pinning and no reply channel, so values can be borrowed and moved out
+/// without copies. Useful for tests and single-threaded embedding.
+///
+/// ```
+/// use kv_store::LocalNode;
+///
+/// let mut node = LocalNode::<&str, Vec<u32>>::new();
+/// node.put("a", vec![1, 2]);
+///
+/// node.get_mut(&"a").unwrap().push(3);
+/// assert_eq!(node.get(&"a"), Some(&vec![1, 2, 3]));
+///
+/// assert_eq!(node.take_owned(&"a"), Some(vec![1, 2, 3]));
+/// assert_eq!(node.get(&"a"), None);
+/// ```
+pub struct LocalNode<K, V> {
+    shard: Shard<K, V>,
+}
+
+impl<K: Key, V: Value> Default for LocalNode<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Key> LocalNode<K, u64> {
+    /// See `Shard::bit_op`.
+    ///
+    /// ```
+    /// use kv_store::{BitOp, LocalNode};
+    ///
+    /// const READ: u64 = 0b001;
+    /// const WRITE: u64 = 0b010;
+    /// const EXEC: u64 = 0b100;
+    ///
+    /// let mut node = LocalNode::<&str, u64>::new();
+    /// assert_eq!(node.bit_op("perms", BitOp::Or, READ | WRITE).unwrap(), READ | WRITE);
+    /// assert_eq!(node.bit_op("perms", BitOp::Xor, EXEC).unwrap(), READ | WRITE | EXEC);
+    /// assert_eq!(node.bit_op("perms", BitOp::And, !WRITE).unwrap(), READ | EXEC);
+    /// assert_eq!(node.bit_op("other", BitOp::And, READ).unwrap(), 0);
+    /// assert_eq!(node.get(&"perms"), Some(&(READ | EXEC)));
+    /// ```
+    pub fn bit_op(&mut self, key: K, op: BitOp, operand: u64) -> KVResult<u64> {
+        self.shard.bit_op(key, op, operand)
+    }
+
+    /// See `Shard::decr_del`.
+    ///
+    /// ```
+    /// use kv_store::LocalNode;
+    ///
+    /// let mut node = LocalNode::<&str, u64>::new();
+    /// node.put("refs", 2);
+    ///
+    /// assert_eq!(node.decr_del(&"refs"), Some(1));
+    /// assert_eq!(node.get(&"refs"), Some(&1));
+    /// assert_eq!(node.decr_del(&"refs"), Some(0));
+    /// assert_eq!(node.get(&"refs"), None);
+    /// assert_eq!(node.decr_del(&"refs"), None);
+    /// ```
+    pub fn decr_del(&mut self, key: &K) -> Option<u64> {
+        self.shard.decr_del(key)
+    }
+}
+
+impl<K, V> LocalNode<K, V>
+where
+    K: Key,
+    V: Value
+{
+    pub fn new() -> Self {
+        LocalNode { shard: Shard::new(0, 0.into(), 1) }
+    }
+
+    pub fn put(&mut self, key: K, value: V) -> Option<V> {
+        self.shard.insert(key, value)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.shard.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<ValueMut<'_, V>> {
+        self.shard.get_mut(key)
+    }
+
+    /// See `Shard::retain`.
+    ///
+    /// ```
+    /// use kv_store::LocalNode;
+    ///
+    /// let mut node = LocalNode::<u32, &str>::new();
+    /// for (k, status) in [(1, "done"), (2, "open"), (3, "done")] {
+    ///     node.put(k, status);
+    /// }
+    ///
+    /// assert_eq!(node.retain(|_, status| *status != "done"), 2);
+    /// assert_eq!(node.keys().collect::<Vec<_>>(), [&2]);
+    /// ```
+    pub fn retain(&mut self, keep: impl Fn(&K, &V) -> bool) -> usize {
+        self.shard.retain(keep)
+    }
+
+    /// The stored keys, borrowed; values are never read or cloned.
+    ///
+    /// ```
+    /// use kv_store::LocalNode;
+    ///
+    /// let mut node = LocalNode::<u32, Vec<u8>>::new();
+    /// for k in 0..3 {
+    ///     node.put(k, vec![0; 1 << 20]);
+    /// }
+    ///
+    /// let mut keys: Vec<u32> = node.keys().copied().collect();
+    /// keys.sort();
+    /// assert_eq!(keys, [0, 1, 2]);
+    /// ```
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.shard.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.shard.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shard.is_empty()
+    }
+
+    /// See `Shard::copy`.
+    ///
+    /// ```
+    /// use kv_store::{LocalNode, OpOutcome};
+    ///
+    /// let mut node = LocalNode::<&str, String>::new();
+    /// node.put("template", "{}".to_string());
+    ///
+    /// assert_eq!(node.copy(&"template", "doc-1").unwrap(), OpOutcome::Applied);
+    /// assert_eq!(node.get(&"doc-1"), node.get(&"template"));
+    /// assert_eq!(node.get(&"template").map(String::as_str), Some("{}"));
+    /// assert_eq!(node.copy(&"missing", "doc-2").unwrap(), OpOutcome::NotFound);
+    /// ```
+    pub fn copy(&mut self, from: &K, to: K) -> KVResult<OpOutcome> {
+        self.shard.copy(from, to)
+    }
+
+    /// Removes `key` and returns the owned value, like `TAKE` without the
+    /// round trip.
+    pub fn take_owned(&mut self, key: &K) -> Option<V> {
+        self.shard.take(key)
+    }
+
+    /// ```
+    /// use kv_store::LocalNode;
+    ///
+    /// let mut node = LocalNode::<u32, u32>::new();
+    /// node.put(1, 1);
+    /// node.put(1, 2);
+    /// node.get(&1);
+    /// node.get(&2);
+    /// node.get(&3);
+    /// node.shard().delete_if(&1, &9);
+    /// node.take_owned(&4);
+    ///
+    /// let stats = node.stats();
+    /// assert_eq!((stats.inserts, stats.overwrites), (1, 1));
+    /// assert_eq!((stats.hits, stats.misses), (1, 2));
+    /// assert_eq!((stats.condition_failures, stats.deletes_absent), (1, 1));
+    /// ```
+    pub fn stats(&self) -> StatsSnapshot {
+        self.shard.stats.snapshot()
+    }
+
+    /// See `Node::register_fn`.
+    ///
+    /// ```
+    /// use kv_store::{KVError, LocalNode, OpOutcome};
+    ///
+    /// let mut node = LocalNode::<&str, u64>::new();
+    /// node.register_fn("add", |current, arg| current + arg);
+    /// node.put("hits", 2);
+    ///
+    /// assert_eq!(node.shard().apply_fn(&"hits", "add", &3).unwrap(), OpOutcome::Applied);
+    /// assert_eq!(node.get(&"hits"), Some(&5));
+    /// assert_eq!(node.shard().apply_fn(&"nope", "add", &3).unwrap(), OpOutcome::NotFound);
+    /// assert!(matches!(node.shard().apply_fn(&"hits", "mul", &3), Err(KVError::UnknownFunction(_))));
+    /// ```
+    pub fn register_fn(&mut self, name: &str, f: ApplyFn<V>) {
+        self.shard.fns.insert(name.to_string(), f);
+    }
+
+    /// See `Node::register_projection`.
+    ///
+    /// ```
+    /// use kv_store::{KVError, LocalNode};
+    ///
+    /// #[derive(Clone, Debug, Hash, PartialEq, Eq)]
+    /// struct Profile { name: String, avatar: Vec<u8> }
+    ///
+    /// let mut node = LocalNode::<u32, Profile>::new();
+    /// node.register_projection("name_only", |p| Profile { name: p.name.clone(), avatar: Vec::new() });
+    /// node.put(7, Profile { name: "ada".into(), avatar: vec![0; 1 << 16] });
+    ///
+    /// let view = node.shard().get_projected(&7, "name_only").unwrap().unwrap();
+    /// assert_eq!((view.name.as_str(), view.avatar.len()), ("ada", 0));
+    /// assert_eq!(node.shard().get_projected(&8, "name_only").unwrap(), None);
+    /// assert!(matches!(node.shard().get_projected(&7, "nope"), Err(KVError::UnknownFunction(_))));
+    /// ```
+    pub fn register_projection(&mut self, name: &str, project: ProjectFn<V>) {
+        self.shard.views.insert(name.to_string(), project);
+    }
+
+    /// The underlying shard, for operations without a `LocalNode` wrapper.
+    pub fn shard(&mut self) -> &mut Shard<K, V> {
+        &mut self.shard
+    }
+}
+
+/// A started node. Its shards are owned by their threads from here on.
+///
+/// Every step of the lifecycle ends cleanly, however short: a node that is
+/// never started just drops its shards, requests queued before `start` are
+/// served once it starts, and dropping the handle shuts the node down like
+/// `shutdown` does.
+///
+/// ```
+/// use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+/// use kv_store::{core_affinity::CoreId, KVError, Node, NodeConfig, Request};
+///
+/// drop(Node::<u64, u64>::new(0));
+/// Node::<u64, u64>::new(0).start().unwrap().shutdown();
+///
+/// let config = NodeConfig {
+///     core_map: Some(vec![CoreId { id: 0 }; 2]),
+///     queue_capacity: Some(1),
+///     ..Default::default()
+/// };
+/// let mut node = Node::<u64, u64>::with_config(0, config);
+/// let key = (0..).find(|k| node.route(k) == 1).unwrap();
+/// node.send_shard(1, Request::PUT(key, 1)).unwrap();
+/// assert!(matches!(node.send_shard(1, Request::PUT(key, 2)), Err(KVError::QueueFull { dst: 1 })));
+///
+/// let stopped = Arc::new(AtomicBool::new(false));
+/// let mut handle = node.start().unwrap();
+/// let flag = Arc::clone(&stopped);
+/// handle.on_shutdown(move || flag.store(true, Ordering::SeqCst));
+/// while handle.stats()[1].inserts == 0 {
+///     std::thread::yield_now();
+/// }
+/// drop(handle);
+/// assert!(stopped.load(Ordering::SeqCst));
+/// ```
+pub struct NodeHandle<K: Key, V: Value> {
+    id        : usize,
+    handles   : Vec<JoinHandle<()>>,
+    ready     : Arc<Readiness>,
+    stop      : Arc<AtomicBool>,
+    stats     : Vec<Arc<ShardStats>>,
+    read_only : Arc<AtomicBool>,
+    // behind a lock only so the handle stays `Sync` for `get`
+    hooks     : Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+    router    : Router,
+    // a lock per shard, so a full queue only holds up callers of that shard
+    ingress   : Vec<Mutex<Producer<Request<K, V>>>>,
+    unpinned  : Vec<usize>,
+}
+
+impl<K: Key, V: Value> NodeHandle<K, V> {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.stats.len()
+    }
+
+    /// Shards that tried to pin and failed, which under
+    /// `AffinityPolicy::Prefer` are running unpinned. Empty under `Off`,
+    /// since no shard tries.
+    pub fn unpinned(&self) -> &[usize] {
+        &self.unpinned
+    }
+
+    /// Reads `key` from its shard and returns a clone of the value. The key
+    /// is moved to the shard's thread, so it's taken by value.
+    ///
+    /// This is a round trip: the request queues behind whatever the shard
+    /// is already serving, and the caller blocks until the reply comes
+    /// back. Expect a few microseconds against a busy-polling shard and up
+    /// to the idle strategy's sleep against an idle one, so batch hot
+    /// paths through the shards' own queues instead. Calls for one shard
+    /// take turns on that shard's lock to enqueue, then wait apart, so a
+    /// full queue on one shard doesn't hold up reads of another. A shard
+    /// that has died reads as `None`.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, Node, NodeConfig};
+    ///
+    /// let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 2]), ..Default::default() };
+    /// let mut node = Node::<u64, String>::with_config(0, config);
+    /// node.preload(1, "one".to_string()).unwrap();
+    /// node.preload(2, "two".to_string()).unwrap();
+    ///
+    /// let handle = node.start().unwrap();
+    /// assert_eq!(handle.get(1).as_deref(), Some("one"));
+    /// assert_eq!(handle.get(3), None);
+    ///
+    /// std::thread::scope(|s| {
+    ///     let one = s.spawn(|| handle.get(1));
+    ///     let two = s.spawn(|| handle.get(2));
+    ///     assert_eq!(one.join().unwrap().as_deref(), Some("one"));
+    ///     assert_eq!(two.join().unwrap().as_deref(), Some("two"));
+    /// });
+    /// handle.shutdown();
+    /// ```
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, Node, NodeConfig, Request};
+    ///
+    /// let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 2]), ..Default::default() };
+    /// let mut node = Node::<u64, u64>::with_config(0, config);
+    /// node.register_fn("boom", |_, _| panic!("boom"));
+    /// let key = (0..).find(|k| node.route(k) == 1).unwrap();
+    /// node.preload(key, 1).unwrap();
+    /// node.send_shard(1, Request::APPLY_FN { key, fn_name: "boom".into(), arg: 0 }).unwrap();
+    ///
+    /// let handle = node.start().unwrap();
+    /// assert_eq!(handle.get(key), None);
+    /// drop(handle);
+    /// ```
+    pub fn get(&self, key: K) -> Option<V> {
+        let shard = self.router.route(&key);
+        let (reply, rx) = mpsc::channel();
+        self.call(shard, Request::GET_REPLY { key, reply }, rx).flatten()
+    }
+
+    /// Removes `key` on its shard and returns the value, a round trip like
+    /// `get`. Of several racing takes for one key only the first gets `Some`.
+    ///
+    /// ```
+    /// use kv_store::Node;
+    ///
+    /// let mut node = Node::<u64, u64>::new(0);
+    /// node.preload(7, 70).unwrap();
+    ///
+    /// let handle = node.start().unwrap();
+    /// assert_eq!(handle.take(7), Some(70));
+    /// assert_eq!(handle.take(7), None);
+    /// handle.shutdown();
+    /// ```
+    pub fn take(&self, key: K) -> Option<V> {
+        let shard = self.router.route(&key);
+        let (reply, rx) = mpsc::channel();
+        self.call(shard, Request::TAKE_REPLY { key, reply }, rx).flatten()
+    }
+
+    /// Stores `value` at `key` unless it's already set and returns the
+    /// outcome, a round trip like `get`; `ShardDied` if that shard has
+    /// stopped. Of several racing inserts exactly one is `Applied`, and its
+    /// value is the one kept.
+    ///
+    /// ```
+    /// use kv_store::{Node, OpOutcome};
+    ///
+    /// let handle = Node::<u64, u64>::new(0).start().unwrap();
+    /// assert_eq!(handle.put_if_absent(1, 10).unwrap(), OpOutcome::Applied);
+    /// assert_eq!(handle.put_if_absent(1, 20).unwrap(), OpOutcome::ConditionFailed);
+    /// assert_eq!(handle.get(1), Some(10));
+    /// handle.shutdown();
+    /// ```
+    pub fn put_if_absent(&self, key: K, value: V) -> KVResult<OpOutcome> {
+        let shard = self.router.route(&key);
+        let (reply, rx) = mpsc::channel();
+        self.call(shard, Request::PUT_IF_ABSENT_REPLY { key, value, reply }, rx).ok_or(KVError::ShardDied)?
+    }
+
+    /// Reads `keys` in one step on their shard, so no write lands between
+    /// the reads. The keys must share a shard (see `Node::route_all`);
+    /// otherwise this fails with `CrossShard` and reads nothing. A round trip
+    /// like `get`.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, KVError, Node, NodeConfig};
+    ///
+    /// let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 2]), ..Default::default() };
+    /// let mut node = Node::<u64, u64>::with_config(0, config);
+    /// let mut ones = (0..).filter(|k| node.route(k) == 1);
+    /// let (a, b) = (ones.next().unwrap(), ones.next().unwrap());
+    /// let other = (0..).find(|k| node.route(k) == 0).unwrap();
+    /// node.preload(a, 1).unwrap();
+    ///
+    /// let handle = node.start().unwrap();
+    /// assert_eq!(handle.multi_get_atomic(vec![a, b]).unwrap(), [Some(1), None]);
+    /// assert!(matches!(handle.multi_get_atomic(vec![a, other]), Err(KVError::CrossShard)));
+    /// assert!(matches!(handle.multi_get_atomic(vec![]), Err(KVError::EmptyBatch)));
+    /// handle.shutdown();
+    /// ```
+    pub fn multi_get_atomic(&self, keys: Vec<K>) -> KVResult<Vec<Option<V>>> {
+        let shard = self.router.route(keys.first().ok_or(KVError::EmptyBatch)?);
+        let (reply, rx) = mpsc::channel();
+        self.call(shard, Request::MULTI_GET_ATOMIC { keys, reply }, rx).ok_or(KVError::ShardDied)?
+    }
+
+    /// Copies `from` into `to` on `from`'s shard and returns the outcome, a
+    /// round trip like `get`; `ShardDied` if that shard has stopped. A `Forwarded`
+    /// copy is visible once `to`'s shard has served it.
+    ///
+    /// ```
+    /// use kv_store::{core_affinity::CoreId, Node, NodeConfig, OpOutcome};
+    ///
+    /// let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 2]), ..Default::default() };
+    /// let mut node = Node::<u64, u64>::with_config(0, config);
+    /// let mut ones = (0..).filter(|k| node.route(k) == 1);
+    /// let (from, near) = (ones.next().unwrap(), ones.next().unwrap());
+    /// let far = (0..).find(|k| node.route(k) == 0).unwrap();
+    /// node.preload(from, 5).unwrap();
+    ///
+    /// let handle = node.start().unwrap();
+    /// assert_eq!(handle.copy(from, near).unwrap(), OpOutcome::Applied);
+    /// assert_eq!(handle.get(near), Some(5));
+    /// assert_eq!(handle.copy(from, far).unwrap(), OpOutcome::Forwarded);
+    /// handle.shutdown();
+    /// ```
+    pub fn copy(&self, from: K, to: K) -> KVResult<OpOutcome> {
+        let shard = self.router.route(&from);
+        let (reply, rx) = mpsc::channel();
+        self.call(shard, Request::COPY_REPLY { from, to, reply }, rx).ok_or(KVError::ShardDied)?
+    }
+
+    /// The shard owning `key`, the same as `Node::route` before start.
+    pub fn route(&self, key: &K) -> usize {
+        self.router.route(key)
+    }
+
+    /// Queues `req` on shard `shard_id`'s ingress queue without waiting for
+    /// it to be served, like `Node::send_shard` on a running node.
+    pub fn send_shard(&self, shard_id: usize, req: Request<K, V>) -> KVResult<()> {
+        let queue = self.ingress.get(shard_id).ok_or(KVError::UnknownShard(shard_id))?;
+        queue
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(req)
+            .map_err(|_r| KVError::QueueFull { dst: shard_id })
+    }
+
+    // queues `request` for `shard` and waits for what it sends back on the
+    // other end of `rx`; `None` if the shard died first
+    fn call<R>(&self, shard: usize, mut request: Request<K, V>, rx: mpsc::Receiver<R>) -> Option<R> {
+        let queue = &self.ingress[shard];
+        loop {
+            {
+                let mut producer = queue.lock().unwrap_or_else(|e| e.into_inner());
+                // a dead shard's queue still takes pushes, but nothing pops them
+                if producer.is_abandoned() {
+                    return None;
+                }
+                match producer.push(request) {
+                    Ok(()) => break,
+                    Err(PushError::Full(back)) => request = back,
+                }
+            }
+            thread::yield_now();
+        }
+
+        // the reply sender sits in the queue until popped, so a shard that
+        // dies first never disconnects it; watch the queue instead
+        loop {
+            match rx.recv_timeout(std::time::Duration::from_millis(1)) {
+                Ok(value) => return Some(value),
+                Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+                Err(mpsc::RecvTimeoutError::Timeout) if self.abandoned(shard) => return rx.try_recv().ok(),
+                Err(mpsc::RecvTimeoutError::Timeout) => {},
+            }
+        }
+    }
+
+    fn abandoned(&self, shard: usize) -> bool {
+        self.ingress[shard].lock().unwrap_or_else(|e| e.into_inner()).is_abandoned()
+    }
+
+    /// Refuses write requests on every shard with `KVError::ReadOnly` until
+    /// cleared, while reads go on. Requests already being served finish.
+    ///
+    /// ```
+    /// use kv_store::Node;
+    ///
+    /// let handle = Node::<u64, u64>::new(0).start().unwrap();
+    /// handle.set_read_only(true);
+    /// assert!(handle.is_read_only());
+    /// handle.set_read_only(false);
+    /// handle.shutdown();
+    /// ```
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Release);
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Acquire)
+    }
+
+    /// Blocks until every shard has pinned itself and entered its loop, so
+    /// callers don't have to sleep and hope after `start`.
+    ///
+    /// ```
+    /// use kv_store::Node;
+    ///
+    /// let handle = Node::<u64, u64>::new(0).start().unwrap();
+    /// handle.wait_ready();
+    /// assert!(handle.is_ready());
+    /// ```
+    pub fn wait_ready(&self) {
+        self.ready.wait_for(self.handles.len());
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.get() >= self.handles.len()
+    }
+
+    /// Operation counters per shard, indexed by shard id. Cheap enough to
+    /// poll while the node is serving.
+    pub fn stats(&self) -> Vec<StatsSnapshot> {
+        self.stats.iter().map(|stats| stats.snapshot()).collect()
+    }
+
+    /// `stats`, zeroing the counters so the next call covers only what
+    /// happened since this one.
+    ///
+    /// ```
+    /// use kv_store::Node;
+    ///
+    /// let mut node = Node::<u64, u64>::new(0);
+    /// node.preload(1, 1).unwrap();
+    /// node.warmup([1, 2]);
+    ///
+    /// let handle = node.start().unwrap();
+    /// handle.wait_ready();
+    /// let total = |stats: Vec<kv_store::stats::StatsSnapshot>| stats.iter().map(|s| s.reads()).sum::<u64>();
+    /// assert_eq!(total(handle.metrics_reset()), 2);
+    /// assert_eq!(total(handle.stats()), 0);
+    /// handle.shutdown();
+    /// ```
+    pub fn metrics_reset(&self) -> Vec<StatsSnapshot> {
+        self.stats.iter().map(|stats| stats.reset()).collect()
+    }
+
+    /// The optional features and request kinds compiled into this build.
+    ///
+    /// ```
+    /// use kv_store::Node;
+    ///
+    /// let handle = Node::<u64, u64>::new(0).start().unwrap();
+    /// let caps = handle.capabilities();
+    /// assert_eq!(caps.metadata, cfg!(feature = "metadata"));
+    /// assert_eq!(caps.checksums, cfg!(feature = "checksums"));
+    /// assert_eq!(caps.requests.contains(&"GET_META"), cfg!(feature = "metadata"));
+    /// assert!(caps.requests.contains(&"PUT"));
+    /// handle.shutdown();
+    /// ```
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::current()
+    }
+
+    /// Registers cleanup to run once every shard thread has exited, on the
+    /// thread that calls `join` or `shutdown`. Hooks run in the order they
+    /// were added.
+    ///
+    /// ```
+    /// use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+    /// use kv_store::Node;
+    ///
+    /// let runs = Arc::new(AtomicUsize::new(0));
+    /// let mut handle = Node::<u64, u64>::new(0).start().unwrap();
+    /// let counter = Arc::clone(&runs);
+    /// handle.on_shutdown(move || { counter.fetch_add(1, Ordering::SeqCst); });
+    ///
+    /// assert_eq!(runs.load(Ordering::SeqCst), 0);
+    /// handle.shutdown();
+    /// assert_eq!(runs.load(Ordering::SeqCst), 1);
+    /// ```
+    pub fn on_shutdown(&mut self, hook: impl FnOnce() + Send + 'static) {
+        self.hooks.get_mut().unwrap_or_else(|e| e.into_inner()).push(Box::new(hook));
+    }
+
+    /// Blocks until every shard thread has exited, then runs the shutdown
+    /// hooks. If a shard panicked, its panic is raised here after the hooks.
+    ///
+    /// A panic in a registered function takes its shard down and lands
+    /// here. So does a request sent to the wrong shard, but only in debug
+    /// builds, which check that every key a shard receives routes to it.
+    ///
+    /// ```should_panic
+    /// use kv_store::{core_affinity::CoreId, Node, NodeConfig, Request};
+    ///
+    /// let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 2]), ..Default::default() };
+    /// let mut node = Node::<u64, u64>::with_config(0, config);
+    /// node.register_fn("boom", |_, _| panic!("boom"));
+    /// let key = (0..).find(|k| node.route(k) == 1).unwrap();
+    /// node.preload(key, 1).unwrap();
+    /// node.send_shard(1, Request::APPLY_FN { key, fn_name: "boom".into(), arg: 0 }).unwrap();
+    ///
+    /// node.start().unwrap().shutdown();
+    /// ```
+    pub fn join(mut self) {
+        if let Some(payload) = self.finish() {
+            std::panic::resume_unwind(payload);
+        }
+    }
+
+    // joins the threads and runs the hooks, handing back the first panic
+    fn finish(&mut self) -> Option<Box<dyn std::any::Any + Send>> {
+        let mut panic = None;
+        for handle in std::mem::take(&mut self.handles) {
+            if let Err(payload) = handle.join() {
+                panic.get_or_insert(payload);
+            }
+        }
+        for hook in std::mem::take(self.hooks.get_mut().unwrap_or_else(|e| e.into_inner())) {
+            hook();
+        }
+        panic
+    }
+
+    /// Stops every shard once it has served what's already queued, then
+    /// joins the threads.
+    pub fn shutdown(self) {
+        self.stop.store(true, Ordering::Release);
+        self.join();
+    }
+}
+
+// a dropped handle shuts down too, so shard threads never outlive it
+impl<K: Key, V: Value> Drop for NodeHandle<K, V> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        self.finish();
     }
 }
 
@@ -1,5 +1,10 @@
-use kv_store::{make_node, Request};
+use kv_store::Node;
 
 fn main() {
+    let mut node = Node::<u64, u64>::new(0);
+    node.preload(1, 1).ok();
 
+    let handle = node.start().expect("failed to start node");
+    handle.wait_ready();
+    handle.shutdown();
 }
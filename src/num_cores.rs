@@ -1,9 +1,8 @@
-#[allow(dead_code)]
 // Calculation for the number of Logical CPU cores
 // Works on MacOS, BSD, Linux
 
-// impl_as( Type =>
-//     as_u64, u64,
+// impl_as!(newtype = Type =>
+//     as_u64: u64,
 // )
 macro_rules! impl_as {
     (newtype = $ty:ty => $($method:ident : $cast:ty),+ $(,)?) => {
@@ -19,22 +18,65 @@ macro_rules! impl_as {
 }
 
 pub mod num_cpus {
-    // #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-    // pub struct LogicalCores(usize);
+    use std::cell::Cell;
 
-    // impl_as!(newtype = LogicalCores =>
-    //     as_u8   : u8,
-    //     as_i64  : u64,
-    //     as_usize: usize,
-    // );
+    /// A count of logical cores, kept apart from other `usize`s. The
+    /// conversions truncate like `as`, which no real core count comes near.
+    ///
+    /// ```
+    /// use kv_store::num_cores::num_cpus;
+    ///
+    /// num_cpus::set_override(Some(12));
+    /// let cores = num_cpus::detect_typed();
+    /// assert_eq!(cores.as_usize(), num_cpus::detect());
+    /// assert_eq!((cores.as_u8(), cores.as_u64(), cores.as_i64()), (12, 12, 12));
+    /// ```
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct LogicalCores(usize);
+
+    impl_as!(newtype = LogicalCores =>
+        as_u8   : u8,
+        as_u64  : u64,
+        as_i64  : i64,
+        as_usize: usize,
+    );
+
+    thread_local! {
+        static OVERRIDE: Cell<Option<usize>> = const { Cell::new(None) };
+    }
 
     #[inline]
     pub fn detect() -> usize {
-        get_num_cpus()
+        OVERRIDE.with(Cell::get).unwrap_or_else(get_num_cpus)
     }
 
-    #[cfg(any(target_os = "linux"))]
+    /// `detect`, as a `LogicalCores`.
+    #[inline]
+    pub fn detect_typed() -> LogicalCores {
+        LogicalCores(detect())
+    }
+
+    /// Makes `detect` on the current thread report `count` instead of the real
+    /// core count, until reset with `None`. Meant for tests that depend on the
+    /// shard count.
+    ///
+    /// ```
+    /// use kv_store::{num_cores::num_cpus, Node};
+    ///
+    /// num_cpus::set_override(Some(4));
+    /// let node = Node::<u64, u64>::new(0);
+    /// assert!(format!("{node:?}").contains("active_shards: 4"));
+    ///
+    /// num_cpus::set_override(None);
+    /// ```
+    pub fn set_override(count: Option<usize>) {
+        OVERRIDE.with(|o| o.set(count));
+    }
+
+    #[cfg(target_os = "linux")]
     fn get_num_cpus() -> usize {
+        use std::mem;
+
         let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
         if unsafe { libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) } == 0 {
             let mut count: u32 = 0;
@@ -124,4 +166,63 @@ pub mod num_cpus {
         let cpus = unsafe { libc::sysconf(CONF_NAME) };
         if cpus < 1 { 1 } else { cpus as usize }
     }
+
+    // everything else: whatever std can find out, at least 1
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "android",
+        target_os = "aix",
+        target_os = "solaris",
+        target_os = "illumos",
+        target_os = "fuchsia"
+    )))]
+    fn get_num_cpus() -> usize {
+        std::thread::available_parallelism().map_or(1, std::num::NonZero::get)
+    }
+}
+
+pub mod numa {
+    use std::cell::Cell;
+
+    thread_local! {
+        static OVERRIDE: Cell<Option<usize>> = const { Cell::new(None) };
+    }
+
+    /// Number of NUMA nodes, 1 where the platform doesn't expose any.
+    #[inline]
+    pub fn node_count() -> usize {
+        OVERRIDE.with(Cell::get).unwrap_or_else(get_node_count)
+    }
+
+    /// Makes `node_count` on the current thread report `count` instead of
+    /// the real node count, until reset with `None`. Meant for tests of
+    /// `NodeConfig::numa_local_maps` on single-node machines.
+    pub fn set_override(count: Option<usize>) {
+        OVERRIDE.with(|o| o.set(count));
+    }
+
+    #[cfg(target_os = "linux")]
+    fn get_node_count() -> usize {
+        let is_node = |name: &str| name.strip_prefix("node").is_some_and(|id| id.parse::<usize>().is_ok());
+
+        std::fs::read_dir("/sys/devices/system/node")
+            .map(|dir| {
+                dir.flatten()
+                    .filter(|entry| entry.file_name().to_str().is_some_and(is_node))
+                    .count()
+                    .max(1)
+            })
+            .unwrap_or(1)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn get_node_count() -> usize {
+        1
+    }
 }
@@ -0,0 +1,339 @@
+//! Maps keys onto shards.
+//!
+//! Without weights a key goes to `hash % num_shards`. With weights the hash
+//! space is cut into one contiguous range per shard, sized by its weight, so a
+//! shard with twice the weight owns twice the keys.
+//!
+//! With a partition count, keys go to `hash % partitions` and a fixed table
+//! maps partitions to shards (round-robin, or by weight). Rescaling then only
+//! has to move whole partitions instead of rehashing every key.
+//!
+//! The hash is seeded per node, so nodes with different seeds spread the same
+//! keys differently. That isn't a defence against chosen keys: the default
+//! FNV-1a is unkeyed and its final mix is invertible, so anyone who knows or
+//! guesses the seed can pick keys that all land on one shard. For untrusted
+//! keys use [`RoutingHasher::Sip`] with a seed kept secret, which makes
+//! placement hard to predict, and keep the default [`BucketHasher::Random`]
+//! so keys can't be piled into one bucket of a shard's map either.
+//!
+//! Routing hashes with a fixed FNV-1a by default, not `DefaultHasher`, so a
+//! key lands on the same shard in every process and with every toolchain.
+//! `DefaultHasher` is only used for in-process bookkeeping (bloom bits, change
+//! records). The shard maps bucket with their own hasher, `BucketHasher`,
+//! which never affects placement.
+
+use std::hash::{BuildHasher, DefaultHasher, Hash, Hasher, RandomState};
+
+/// How keys are spread over shards.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Partitioner {
+    /// `hash % num_shards`.
+    Modulo,
+    /// Each shard owns the hash range ending (exclusively) at its bound.
+    Weighted { bounds: Vec<u64> },
+    /// Partition `hash % table.len()` lives on shard `table[partition]`.
+    Partitioned { table: Vec<usize> },
+}
+
+/// Which hash picks a key's shard. This is the only hash that decides
+/// placement.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoutingHasher {
+    /// [`RouteHasher`]. Routes are the same across processes and toolchains,
+    /// but keys can be chosen to land on one shard.
+    #[default]
+    Fnv,
+    /// std's SipHash-1-3 with fixed keys. Spreads keys that differ in only a
+    /// few bits more evenly, but std may change it between releases, so
+    /// routes only hold for one toolchain. With a secret seed, the choice
+    /// for keys from untrusted clients.
+    Sip,
+}
+
+/// Which hash the shard maps bucket keys with. Keys are already on their
+/// shard by then, so this only changes lookup speed, never placement.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BucketHasher {
+    /// std's randomly keyed SipHash, which keys chosen to collide can't beat.
+    #[default]
+    Random,
+    /// [`RouteHasher`], unseeded. Quicker on short keys, but anyone picking
+    /// keys can pile them into one bucket.
+    Fnv,
+}
+
+impl BucketHasher {
+    pub(crate) fn state(self) -> BucketState {
+        match self {
+            BucketHasher::Random => BucketState::Random(RandomState::new()),
+            BucketHasher::Fnv => BucketState::Fnv,
+        }
+    }
+}
+
+// the shard maps' `BuildHasher`, picked at runtime from `BucketHasher`
+#[derive(Debug, Clone)]
+pub(crate) enum BucketState {
+    Random(RandomState),
+    Fnv,
+}
+
+impl Default for BucketState {
+    fn default() -> Self {
+        BucketHasher::default().state()
+    }
+}
+
+impl BuildHasher for BucketState {
+    type Hasher = BucketHash;
+
+    fn build_hasher(&self) -> BucketHash {
+        match self {
+            BucketState::Random(state) => BucketHash::Sip(state.build_hasher()),
+            BucketState::Fnv => BucketHash::Fnv(RouteHasher::default()),
+        }
+    }
+}
+
+pub(crate) enum BucketHash {
+    Sip(DefaultHasher),
+    Fnv(RouteHasher),
+}
+
+impl Hasher for BucketHash {
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            BucketHash::Sip(hasher) => hasher.write(bytes),
+            BucketHash::Fnv(hasher) => hasher.write(bytes),
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        match self {
+            BucketHash::Sip(hasher) => hasher.finish(),
+            BucketHash::Fnv(hasher) => hasher.finish(),
+        }
+    }
+}
+
+/// The hasher behind routing: FNV-1a with a 64-bit finalizer so the high
+/// bits weighted ranges rely on are mixed too. Its output is fixed, unlike
+/// `DefaultHasher`'s, which std is free to change between releases. Integers
+/// hash as their native-endian bytes, so routes match between targets of the
+/// same endianness.
+#[derive(Debug, Clone, Copy)]
+pub struct RouteHasher(u64);
+
+impl Default for RouteHasher {
+    fn default() -> Self {
+        RouteHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for RouteHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        // murmur3's fmix64
+        let mut h = self.0;
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        h ^ (h >> 33)
+    }
+}
+
+/// A read-only description of a node's routing, enough for a client to route
+/// keys itself. Hashes are `hasher` over the seed as a `u64` followed by the
+/// key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutingInfo {
+    pub partitioner : Partitioner,
+    pub num_shards  : usize,
+    pub seed        : u64,
+    pub hasher      : RoutingHasher,
+}
+
+/// The owning shard of each key in a batch, worked out once so repeated
+/// operations on the same keys skip the hashing. Only valid for the node it
+/// came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutePlan {
+    shards     : Vec<usize>,
+    num_shards : usize,
+}
+
+impl RoutePlan {
+    /// The shard of the `i`th key.
+    pub fn shard(&self, i: usize) -> usize {
+        self.shards[i]
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    /// Sorts `items`, one per planned key and in the same order, into one
+    /// list per shard.
+    ///
+    /// # Panics
+    ///
+    /// If there aren't exactly as many items as planned keys.
+    pub fn split<T>(&self, items: impl IntoIterator<Item = T>) -> Vec<Vec<T>> {
+        let mut groups: Vec<Vec<T>> = (0..self.num_shards).map(|_| Vec::new()).collect();
+        let mut count = 0;
+        for (item, &shard) in items.into_iter().zip(&self.shards) {
+            groups[shard].push(item);
+            count += 1;
+        }
+        assert_eq!(count, self.shards.len(), "route plan is for {} items", self.shards.len());
+        groups
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Router {
+    num_shards : usize,
+    seed       : u64,
+    hasher     : RoutingHasher,
+    // exclusive upper end of each shard's slice of the hash space
+    bounds     : Option<Vec<u64>>,
+    // owning shard per partition, overriding bounds when set
+    table      : Option<Vec<usize>>,
+}
+
+impl Router {
+    pub(crate) fn new(num_shards: usize, weights: &[f64], seed: u64, partitions: Option<usize>) -> Self {
+        let bounds = weighted_bounds(num_shards, weights);
+        let table = partitions.map(|partitions| partition_table(num_shards, partitions, bounds.as_deref()));
+        Router { num_shards, seed, hasher: RoutingHasher::default(), bounds, table }
+    }
+
+    pub(crate) fn with_hasher(self, hasher: RoutingHasher) -> Self {
+        Router { hasher, ..self }
+    }
+
+    pub(crate) fn info(&self) -> RoutingInfo {
+        RoutingInfo {
+            partitioner: match (&self.table, &self.bounds) {
+                (Some(table), _) => Partitioner::Partitioned { table: table.clone() },
+                (None, Some(bounds)) => Partitioner::Weighted { bounds: bounds.clone() },
+                (None, None) => Partitioner::Modulo,
+            },
+            num_shards: self.num_shards,
+            seed: self.seed,
+            hasher: self.hasher,
+        }
+    }
+
+    pub(crate) fn plan<K: Hash>(&self, keys: &[K]) -> RoutePlan {
+        RoutePlan {
+            shards: keys.iter().map(|key| self.route(key)).collect(),
+            num_shards: self.num_shards,
+        }
+    }
+
+    pub(crate) fn route<K: Hash>(&self, key: &K) -> usize {
+        if self.num_shards <= 1 {
+            return 0;
+        }
+
+        let hash = match self.hasher {
+            RoutingHasher::Fnv => hash_key_seeded(key, self.seed),
+            RoutingHasher::Sip => {
+                let mut hasher = DefaultHasher::new();
+                hasher.write_u64(self.seed);
+                key.hash(&mut hasher);
+                hasher.finish()
+            },
+        };
+        match (&self.table, &self.bounds) {
+            (Some(table), _) => table[(hash % table.len() as u64) as usize],
+            (None, Some(bounds)) => shard_for_hash(bounds, hash),
+            (None, None) => (hash % self.num_shards as u64) as usize,
+        }
+    }
+
+    // the shards that own any of the hash space, found from the bounds or
+    // the partition table without hashing a key
+    pub(crate) fn owners(&self) -> Vec<usize> {
+        if self.num_shards <= 1 {
+            return vec![0];
+        }
+        let mut owners: Vec<usize> = match (&self.table, &self.bounds) {
+            (Some(table), _) => table.clone(),
+            // each non-empty slice starts at 0 or at the bound before it
+            (None, Some(bounds)) => std::iter::once(0)
+                .chain(bounds.iter().copied())
+                .map(|hash| shard_for_hash(bounds, hash))
+                .collect(),
+            (None, None) => (0..self.num_shards).collect(),
+        };
+        owners.sort_unstable();
+        owners.dedup();
+        owners
+    }
+}
+
+fn shard_for_hash(bounds: &[u64], hash: u64) -> usize {
+    bounds.partition_point(|&bound| bound <= hash).min(bounds.len() - 1)
+}
+
+// with weights, each partition goes where the middle of its share of the
+// hash space would
+fn partition_table(num_shards: usize, partitions: usize, bounds: Option<&[u64]>) -> Vec<usize> {
+    (0..partitions)
+        .map(|p| match bounds {
+            Some(bounds) => shard_for_hash(bounds, ((p as f64 + 0.5) / partitions as f64 * u64::MAX as f64) as u64),
+            None => p % num_shards.max(1),
+        })
+        .collect()
+}
+
+pub(crate) fn hash_key<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub(crate) fn hash_key_seeded<K: Hash>(key: &K, seed: u64) -> u64 {
+    let mut hasher = RouteHasher::default();
+    hasher.write_u64(seed);
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+// shards past the end of `weights` count as 1.0, negative/NaN weights as 0.0
+fn weighted_bounds(num_shards: usize, weights: &[f64]) -> Option<Vec<u64>> {
+    if weights.is_empty() || num_shards == 0 {
+        return None;
+    }
+
+    let weights: Vec<f64> = (0..num_shards)
+        .map(|i| weights.get(i).copied().unwrap_or(1.0))
+        .map(|w| if w > 0.0 { w } else { 0.0 })
+        .collect();
+
+    let total: f64 = weights.iter().sum();
+    if total <= 0.0 || !total.is_finite() {
+        return None;
+    }
+
+    let mut acc = 0.0;
+    Some(weights
+        .iter()
+        .map(|w| {
+            acc += w;
+            (acc / total * u64::MAX as f64) as u64
+        })
+        .collect())
+}
@@ -0,0 +1,96 @@
+//! Per-shard operation counters.
+//!
+//! Each counter is a relaxed atomic bumped once per operation by the owning
+//! shard, so reading them from another thread costs the shard nothing beyond
+//! the increment. Counters are read one by one; a snapshot taken while the
+//! shard is busy may be off by the operations that landed in between.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Default)]
+pub struct ShardStats {
+    hits               : AtomicU64,
+    misses             : AtomicU64,
+    inserts            : AtomicU64,
+    overwrites         : AtomicU64,
+    deletes            : AtomicU64,
+    deletes_absent     : AtomicU64,
+    condition_failures : AtomicU64,
+    slow_ops           : AtomicU64,
+}
+
+/// A point-in-time copy of one shard's counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    pub hits               : u64,
+    pub misses             : u64,
+    /// Writes that created a new key.
+    pub inserts            : u64,
+    /// Writes that replaced an existing value.
+    pub overwrites         : u64,
+    pub deletes            : u64,
+    /// Deletes or takes of keys that weren't there.
+    pub deletes_absent     : u64,
+    /// Conditional ops whose condition didn't hold.
+    pub condition_failures : u64,
+    /// Requests over `NodeConfig::slow_op_threshold`.
+    pub slow_ops           : u64,
+}
+
+impl StatsSnapshot {
+    pub fn reads(&self) -> u64 {
+        self.hits + self.misses
+    }
+}
+
+macro_rules! bump {
+    ($($method:ident => $field:ident),+ $(,)?) => {
+        $(
+            #[inline]
+            pub(crate) fn $method(&self) {
+                self.$field.fetch_add(1, Ordering::Relaxed);
+            }
+        )+
+    };
+}
+
+impl ShardStats {
+    bump!(
+        hit => hits,
+        miss => misses,
+        insert => inserts,
+        overwrite => overwrites,
+        delete => deletes,
+        delete_absent => deletes_absent,
+        condition_failed => condition_failures,
+        slow_op => slow_ops,
+    );
+
+    pub(crate) fn read(&self, found: bool) {
+        if found { self.hit() } else { self.miss() }
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        self.collect(|counter| counter.load(Ordering::Relaxed))
+    }
+
+    /// `snapshot`, zeroing each counter as it's read. Every increment lands
+    /// in exactly one reset's result, though ops racing the reset may split
+    /// across two.
+    pub fn reset(&self) -> StatsSnapshot {
+        self.collect(|counter| counter.swap(0, Ordering::Relaxed))
+    }
+
+    fn collect(&self, read: impl Fn(&AtomicU64) -> u64) -> StatsSnapshot {
+        StatsSnapshot {
+            hits: read(&self.hits),
+            misses: read(&self.misses),
+            inserts: read(&self.inserts),
+            overwrites: read(&self.overwrites),
+            deletes: read(&self.deletes),
+            deletes_absent: read(&self.deletes_absent),
+            condition_failures: read(&self.condition_failures),
+            slow_ops: read(&self.slow_ops),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use kv_store::{core_affinity::CoreId, KVError, Node, NodeConfig, Request, WriteOp};
+
+fn two_shards() -> Node<u64, u64> {
+    let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 2]), ..Default::default() };
+    Node::with_config(0, config)
+}
+
+#[test]
+fn mixed_owner_apply_is_refused_whole() {
+    let mut node = two_shards();
+    let mine = (0..).find(|k| node.route(k) == 1).unwrap();
+    let other = (0..).find(|k| node.route(k) == 0).unwrap();
+    let ops = vec![WriteOp::Put(mine, 1), WriteOp::Put(other, 2)];
+    assert!(matches!(node.route_ops(&ops), Err(KVError::CrossShard)));
+    node.send_shard(1, Request::APPLY(ops)).unwrap();
+
+    let handle = node.start().unwrap();
+    assert_eq!(handle.get(mine), None);
+    assert_eq!(handle.get(other), None);
+    assert!(handle.stats().iter().all(|s| s.inserts == 0));
+    handle.shutdown();
+}
+
+#[test]
+fn multi_get_atomic_never_sees_half_a_batch() {
+    let node = two_shards();
+    let mut ones = (0..).filter(|k| node.route(k) == 1);
+    let (a, b) = (ones.next().unwrap(), ones.next().unwrap());
+    let handle = node.start().unwrap();
+
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            for i in 0..2_000 {
+                let batch = || Request::APPLY(vec![WriteOp::Put(a, i), WriteOp::Put(b, i)]);
+                while handle.send_shard(1, batch()).is_err() {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        for _ in 0..2 {
+            s.spawn(|| {
+                for _ in 0..500 {
+                    let values = handle.multi_get_atomic(vec![a, b]).unwrap();
+                    assert_eq!(values[0], values[1]);
+                }
+            });
+        }
+    });
+    handle.shutdown();
+}
@@ -0,0 +1,25 @@
+use std::time::{Duration, Instant};
+
+use kv_store::{core_affinity::CoreId, Node, NodeConfig, OpOutcome};
+
+#[test]
+fn copy_to_another_shard_is_forwarded_then_lands() {
+    let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 2]), ..Default::default() };
+    let mut node = Node::<u64, u64>::with_config(0, config);
+    let from = (0..).find(|k| node.route(k) == 1).unwrap();
+    let to = (0..).find(|k| node.route(k) == 0).unwrap();
+    node.preload(from, 9).unwrap();
+
+    let handle = node.start().unwrap();
+    assert_eq!(handle.copy(from, to).unwrap(), OpOutcome::Forwarded);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while handle.get(to).is_none() {
+        assert!(Instant::now() < deadline, "forwarded copy never landed");
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(handle.get(to), Some(9));
+    assert_eq!(handle.get(from), Some(9));
+    assert_eq!(handle.stats()[0].inserts, 1);
+    handle.shutdown();
+}
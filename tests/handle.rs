@@ -0,0 +1,41 @@
+use std::time::{Duration, Instant};
+
+use kv_store::{core_affinity::CoreId, Node, NodeConfig, Request};
+
+#[test]
+fn full_queue_on_one_shard_does_not_stall_another() {
+    let config = NodeConfig {
+        core_map: Some(vec![CoreId { id: 0 }; 2]),
+        queue_capacity: Some(1),
+        ..Default::default()
+    };
+    let mut node = Node::<u64, u64>::with_config(0, config);
+    node.register_fn("slow", |current, _| {
+        std::thread::sleep(Duration::from_millis(300));
+        *current
+    });
+    let idle = (0..).find(|k| node.route(k) == 0).unwrap();
+    let busy = (0..).find(|k| node.route(k) == 1).unwrap();
+    node.preload(idle, 1).unwrap();
+    node.preload(busy, 2).unwrap();
+    node.send_shard(1, Request::APPLY_FN { key: busy, fn_name: "slow".into(), arg: 0 }).unwrap();
+
+    let handle = node.start().unwrap();
+    // let shard 1 pick up the slow call, then have a reader fill its queue
+    // and spin on the next push
+    handle.wait_ready();
+    std::thread::sleep(Duration::from_millis(20));
+    std::thread::scope(|s| {
+        let queued = s.spawn(|| handle.get(busy));
+        std::thread::sleep(Duration::from_millis(10));
+        let spinning = s.spawn(|| handle.get(busy));
+        std::thread::sleep(Duration::from_millis(20));
+
+        let started = Instant::now();
+        assert_eq!(handle.get(idle), Some(1));
+        assert!(started.elapsed() < Duration::from_millis(150), "took {:?}", started.elapsed());
+        assert_eq!(queued.join().unwrap(), Some(2));
+        assert_eq!(spinning.join().unwrap(), Some(2));
+    });
+    handle.shutdown();
+}
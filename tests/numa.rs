@@ -0,0 +1,97 @@
+use kv_store::{core_affinity, core_affinity::CoreId, num_cores::numa, Node, NodeConfig};
+
+fn numa_node(shards: Vec<CoreId>) -> Node<u64, u64> {
+    let config = NodeConfig { core_map: Some(shards), numa_local_maps: true, ..Default::default() };
+    Node::with_config(0, config)
+}
+
+#[test]
+fn relocate_keeps_every_entry() {
+    if !core_affinity::is_supported() {
+        return;
+    }
+    let core = core_affinity::get_core_ids().unwrap()[0];
+    let mut node = numa_node(vec![core; 3]);
+    for k in 0..10_000 {
+        node.preload(k, k * 2).unwrap();
+    }
+
+    // pretend to be a two-node machine so start relocates every map
+    numa::set_override(Some(2));
+    let handle = node.start().unwrap();
+    numa::set_override(None);
+
+    assert!(handle.unpinned().is_empty());
+    assert!((0..10_000).all(|k| handle.get(k) == Some(k * 2)));
+    assert_eq!(handle.stats().iter().map(|s| s.inserts).sum::<u64>(), 10_000);
+    handle.shutdown();
+}
+
+// the node `cpu` belongs to, from its `nodeN` link in sysfs
+#[cfg(target_os = "linux")]
+fn node_of_cpu(cpu: usize) -> Option<i32> {
+    std::fs::read_dir(format!("/sys/devices/system/cpu/cpu{cpu}"))
+        .ok()?
+        .flatten()
+        .find_map(|entry| entry.file_name().to_str()?.strip_prefix("node")?.parse().ok())
+}
+
+// the node holding the page at `addr`, from move_pages with no target nodes
+#[cfg(target_os = "linux")]
+fn node_of_page(addr: usize) -> i32 {
+    let page = (addr & !4095) as *mut libc::c_void;
+    let mut status = -1;
+    let pages = [page];
+    let rc = unsafe {
+        libc::syscall(libc::SYS_move_pages, 0, 1, pages.as_ptr(), std::ptr::null::<i32>(), &mut status, 0)
+    };
+    assert_eq!(rc, 0, "move_pages failed");
+    status
+}
+
+// needs a machine with at least two NUMA nodes; run with --ignored
+#[cfg(target_os = "linux")]
+#[test]
+#[ignore]
+fn relocated_maps_live_on_their_shards_node() {
+    let mut cores = Vec::new();
+    let mut nodes = Vec::new();
+    for core in core_affinity::get_core_ids().unwrap_or_default() {
+        if let Some(n) = node_of_cpu(core.id)
+            && !nodes.contains(&n)
+        {
+            cores.push(core);
+            nodes.push(n);
+        }
+    }
+    if nodes.len() < 2 {
+        eprintln!("skipping: fewer than two NUMA nodes");
+        return;
+    }
+
+    let mut node = numa_node(cores);
+    // overwrites the value with the address of its own slot in the map
+    node.register_fn("addr", |value, _| value as *const u64 as u64);
+    let keys: Vec<Vec<u64>> = (0..nodes.len())
+        .map(|shard| (0..).filter(|k| node.route(k) == shard).take(1_000).collect())
+        .collect();
+    for &k in keys.iter().flatten() {
+        node.preload(k, 0).unwrap();
+    }
+
+    let handle = node.start().unwrap();
+    assert!(handle.unpinned().is_empty());
+    for (shard, keys) in keys.iter().enumerate() {
+        let k = keys[keys.len() / 2];
+        let apply = kv_store::Request::APPLY_FN { key: k, fn_name: "addr".to_string(), arg: 0 };
+        handle.send_shard(shard, apply).unwrap();
+        let addr = loop {
+            match handle.get(k) {
+                Some(0) | None => std::thread::yield_now(),
+                Some(addr) => break addr,
+            }
+        };
+        assert_eq!(node_of_page(addr as usize), nodes[shard], "shard {shard}'s map is off its node");
+    }
+    handle.shutdown();
+}
@@ -0,0 +1,16 @@
+// the ownership check is a debug_assert, and doctests are always built with
+// debug assertions even when the crate isn't, so it's tested from here
+#![cfg(debug_assertions)]
+
+use kv_store::{core_affinity::CoreId, Node, NodeConfig, Request};
+
+#[test]
+#[should_panic(expected = "owned by shard 0")]
+fn misrouted_request_panics_on_join() {
+    let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 2]), ..Default::default() };
+    let mut node = Node::<u64, u64>::with_config(0, config);
+    let misrouted = (0..).find(|k| node.route(k) == 0).unwrap();
+    node.send_shard(1, Request::PUT(misrouted, 1)).unwrap();
+
+    node.start().unwrap().shutdown();
+}
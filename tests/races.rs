@@ -0,0 +1,71 @@
+use std::sync::Barrier;
+
+use kv_store::{core_affinity::CoreId, Node, NodeConfig, OpOutcome};
+
+const RACERS: usize = 8;
+
+fn two_shards() -> Node<u64, u64> {
+    let config = NodeConfig { core_map: Some(vec![CoreId { id: 0 }; 2]), ..Default::default() };
+    Node::with_config(0, config)
+}
+
+#[test]
+fn exactly_one_racing_take_wins() {
+    let mut node = two_shards();
+    let keys: Vec<u64> = (0..50).collect();
+    for &k in &keys {
+        node.preload(k, k + 100).unwrap();
+    }
+    let handle = node.start().unwrap();
+
+    for &k in &keys {
+        let barrier = Barrier::new(RACERS);
+        let taken: Vec<Option<u64>> = std::thread::scope(|s| {
+            let racers: Vec<_> = (0..RACERS)
+                .map(|_| s.spawn(|| {
+                    barrier.wait();
+                    handle.take(k)
+                }))
+                .collect();
+            racers.into_iter().map(|racer| racer.join().unwrap()).collect()
+        });
+        let winners: Vec<u64> = taken.into_iter().flatten().collect();
+        assert_eq!(winners, [k + 100], "key {k}");
+        assert_eq!(handle.get(k), None);
+    }
+    let absent: u64 = handle.stats().iter().map(|s| s.deletes_absent).sum();
+    assert_eq!(absent, (keys.len() * (RACERS - 1)) as u64);
+    handle.shutdown();
+}
+
+#[test]
+fn exactly_one_racing_put_if_absent_wins() {
+    let handle = two_shards().start().unwrap();
+
+    for k in 0..50 {
+        let barrier = Barrier::new(RACERS);
+        let outcomes: Vec<(u64, OpOutcome)> = std::thread::scope(|s| {
+            let racers: Vec<_> = (0..RACERS as u64)
+                .map(|value| {
+                    let (handle, barrier) = (&handle, &barrier);
+                    s.spawn(move || {
+                        barrier.wait();
+                        (value, handle.put_if_absent(k, value).unwrap())
+                    })
+                })
+                .collect();
+            racers.into_iter().map(|racer| racer.join().unwrap()).collect()
+        });
+        let winners: Vec<u64> = outcomes
+            .iter()
+            .filter(|(_, outcome)| *outcome == OpOutcome::Applied)
+            .map(|&(value, _)| value)
+            .collect();
+        assert_eq!(winners.len(), 1, "key {k}: {outcomes:?}");
+        assert!(outcomes.iter().all(|(_, o)| matches!(o, OpOutcome::Applied | OpOutcome::ConditionFailed)));
+        assert_eq!(handle.get(k), Some(winners[0]), "key {k}: a later insert overwrote the first");
+    }
+    let failures: u64 = handle.stats().iter().map(|s| s.condition_failures).sum();
+    assert_eq!(failures, 50 * (RACERS as u64 - 1));
+    handle.shutdown();
+}
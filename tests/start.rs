@@ -0,0 +1,49 @@
+use std::{
+    hash::{Hash, Hasher},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use kv_store::{core_affinity, num_cores::numa, KVError, Node, NodeConfig};
+
+const POISONED: u64 = u64::MAX;
+static POISON: AtomicBool = AtomicBool::new(false);
+
+// hashing the poisoned key panics once POISON is set, so a shard holding it
+// dies as soon as `relocate` rehashes its map
+#[derive(Debug, PartialEq, Eq)]
+struct Key(u64);
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        if self.0 == POISONED && POISON.load(Ordering::SeqCst) {
+            panic!("poisoned key hashed");
+        }
+        self.0.hash(state);
+    }
+}
+
+#[test]
+fn shard_dying_before_it_reports_fails_start() {
+    if !core_affinity::is_supported() {
+        return;
+    }
+    let core = core_affinity::get_core_ids().unwrap()[0];
+    let config = NodeConfig {
+        core_map: Some(vec![core; 3]),
+        numa_local_maps: true,
+        ..Default::default()
+    };
+    let mut node = Node::<Key, u64>::with_config(0, config);
+    node.preload(Key(POISONED), 1).unwrap();
+    for k in 0..32 {
+        node.preload(Key(k), k).unwrap();
+    }
+
+    numa::set_override(Some(2));
+    POISON.store(true, Ordering::SeqCst);
+    let started = node.start();
+    POISON.store(false, Ordering::SeqCst);
+    numa::set_override(None);
+
+    assert!(matches!(started, Err(KVError::ShardDied)));
+}